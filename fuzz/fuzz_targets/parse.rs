@@ -0,0 +1,14 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use vmtranslator::{parse_contents, Instruction};
+
+// Feeds arbitrary source through the parser, which must never panic
+fuzz_target!(|data: &[u8]| {
+    if let Ok(source) = std::str::from_utf8(data) {
+        for (i, (line, s)) in parse_contents(source).into_iter().enumerate() {
+            let _ = Instruction::new(s, i, line, "Fuzz");
+        }
+        let _ = Instruction::new(source, 0, 1, "Fuzz");
+    }
+});
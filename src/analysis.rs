@@ -0,0 +1,321 @@
+use crate::command::parse_index;
+use crate::{strip_bom, Instruction, Segment};
+use std::collections::{HashMap, HashSet};
+use std::ops::Range;
+
+/// The RAM used by a program, as the number of slots used
+/// in each virtual memory segment (one past the highest index accessed)
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct RamFootprint {
+    pub argument: usize,
+    pub local: usize,
+    pub this: usize,
+    pub that: usize,
+    pub temp: usize,
+    pub pointer: usize,
+    /// Number of distinct static variables across all files
+    pub statics: usize,
+}
+
+/// Returns which parts of RAM the push and pop instructions of a program access
+pub fn ram_footprint(instructions: &[Instruction]) -> RamFootprint {
+    let mut footprint = RamFootprint::default();
+    let mut statics = HashSet::new();
+    for x in instructions
+        .iter()
+        .filter(|x| matches!(x.operation, "push" | "pop"))
+    {
+        let Some(segment) = x.arg1.and_then(Segment::from_name) else {
+            continue;
+        };
+        let Some(Ok(index)) = x.arg2.map(|x| parse_index(segment, x)) else {
+            continue;
+        };
+        let index = index as usize;
+        let slots = match segment {
+            Segment::Argument => &mut footprint.argument,
+            Segment::Local => &mut footprint.local,
+            Segment::This => &mut footprint.this,
+            Segment::That => &mut footprint.that,
+            Segment::Temp => &mut footprint.temp,
+            Segment::Pointer => &mut footprint.pointer,
+            Segment::Static => {
+                statics.insert((x.file, index));
+                continue;
+            }
+            Segment::Constant => continue,
+        };
+        *slots = (*slots).max(index + 1);
+    }
+    footprint.statics = statics.len();
+    footprint
+}
+
+/// Returns, for each instruction, its frame and the index of the basic block it
+/// starts within the frame, or None if it continues the block of the previous one.
+/// A label or function starts a block, and a goto, if-goto or return ends one
+pub(crate) fn basic_block_starts<'a>(
+    instructions: &[&Instruction<'a>],
+) -> Vec<Option<(&'a str, usize)>> {
+    let mut counts = HashMap::new();
+    instructions
+        .iter()
+        .enumerate()
+        .map(|(i, x)| {
+            let starts = match i.checked_sub(1).map(|j| instructions[j]) {
+                Some(previous) => {
+                    matches!(x.operation, "label" | "function")
+                        || matches!(previous.operation, "goto" | "if-goto" | "return")
+                        || previous.file != x.file
+                }
+                None => true,
+            };
+            let frame = x.frame.unwrap_or("global");
+            starts.then(|| {
+                let count = counts.entry((x.file, frame)).or_insert(0);
+                *count += 1;
+                (frame, *count - 1)
+            })
+        })
+        .collect()
+}
+
+/// A function defined by a `function` instruction
+#[derive(Clone, Debug, PartialEq)]
+pub struct FunctionInfo<'a> {
+    pub name: &'a str,
+    /// Number of local variables, 0 if the count isn't a valid number
+    pub locals: usize,
+    pub file: &'a str,
+    /// Indices of the function's instructions, from its `function` instruction
+    /// up to the next function of the same file or the end of the file
+    pub span: Range<usize>,
+}
+
+/// Returns the functions defined by the instructions, in the order they are defined
+pub fn defined_functions<'a>(instructions: &[Instruction<'a>]) -> Vec<FunctionInfo<'a>> {
+    instructions
+        .iter()
+        .enumerate()
+        .filter(|(_, x)| x.operation == "function")
+        .map(|(i, x)| {
+            let len = instructions[i + 1..]
+                .iter()
+                .take_while(|y| y.file == x.file && y.operation != "function")
+                .count();
+            FunctionInfo {
+                name: x.arg1.unwrap_or_default(),
+                locals: x.arg2.and_then(|n| n.parse().ok()).unwrap_or_default(),
+                file: x.file,
+                span: i..i + 1 + len,
+            }
+        })
+        .collect()
+}
+
+/// The functions of the standard Jack OS, provided by the OS VM files
+/// or the VM emulator's built-in implementations
+const JACK_OS_FUNCTIONS: [&str; 49] = [
+    "Math.init",
+    "Math.abs",
+    "Math.multiply",
+    "Math.divide",
+    "Math.min",
+    "Math.max",
+    "Math.sqrt",
+    "String.new",
+    "String.dispose",
+    "String.length",
+    "String.charAt",
+    "String.setCharAt",
+    "String.appendChar",
+    "String.eraseLastChar",
+    "String.intValue",
+    "String.setInt",
+    "String.backSpace",
+    "String.doubleQuote",
+    "String.newLine",
+    "Array.new",
+    "Array.dispose",
+    "Output.init",
+    "Output.moveCursor",
+    "Output.printChar",
+    "Output.printString",
+    "Output.printInt",
+    "Output.println",
+    "Output.backSpace",
+    "Screen.init",
+    "Screen.clearScreen",
+    "Screen.setColor",
+    "Screen.drawPixel",
+    "Screen.drawLine",
+    "Screen.drawRectangle",
+    "Screen.drawCircle",
+    "Keyboard.init",
+    "Keyboard.keyPressed",
+    "Keyboard.readChar",
+    "Keyboard.readLine",
+    "Keyboard.readInt",
+    "Memory.init",
+    "Memory.peek",
+    "Memory.poke",
+    "Memory.alloc",
+    "Memory.deAlloc",
+    "Sys.init",
+    "Sys.halt",
+    "Sys.error",
+    "Sys.wait",
+];
+
+/// Returns the functions called but neither defined by the instructions, part of
+/// the Jack OS nor among the external functions defined outside the translated files,
+/// sorted by name, each with the files calling it in order of appearance
+pub fn unresolved_calls<'a>(
+    instructions: &[Instruction<'a>],
+    external: &[String],
+) -> Vec<(&'a str, Vec<&'a str>)> {
+    let defined = defined_functions(instructions)
+        .iter()
+        .map(|x| x.name)
+        .collect::<HashSet<&str>>();
+    let mut unresolved: Vec<(&str, Vec<&str>)> = vec![];
+    for x in instructions.iter().filter(|x| x.operation == "call") {
+        let Some(callee) = x.arg1 else {
+            continue;
+        };
+        if defined.contains(callee)
+            || JACK_OS_FUNCTIONS.contains(&callee)
+            || external.iter().any(|x| x == callee)
+        {
+            continue;
+        }
+        match unresolved.iter_mut().find(|(name, _)| *name == callee) {
+            Some((_, callers)) if callers.contains(&x.file) => (),
+            Some((_, callers)) => callers.push(x.file),
+            None => unresolved.push((callee, vec![x.file])),
+        }
+    }
+    unresolved.sort_by_key(|(name, _)| *name);
+    unresolved
+}
+
+/// Returns the documentation of the functions defined in the source: the block
+/// of comment lines directly above each `function` declaration, without
+/// the `//` markers. A blank line between the comments and the declaration
+/// detaches them, and functions without comments are left out
+pub fn function_docs(source: &str) -> HashMap<String, String> {
+    let mut docs = HashMap::new();
+    let mut block: Vec<&str> = vec![];
+    for line in strip_bom(source).lines().map(str::trim) {
+        if let Some(comment) = line.strip_prefix("//") {
+            block.push(comment.strip_prefix(' ').unwrap_or(comment).trim_end());
+            continue;
+        }
+        let mut parts = line.split("//").next().unwrap().split_whitespace();
+        if let (Some("function"), Some(name), false) =
+            (parts.next(), parts.next(), block.is_empty())
+        {
+            docs.insert(
+                name.to_string(),
+                block.join("\n").trim_matches('\n').to_string(),
+            );
+        }
+        block.clear();
+    }
+    docs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse_program;
+
+    /// Returns the program of a single file named Main
+    fn program(source: &str) -> Vec<(String, String)> {
+        vec![("Main".to_string(), source.to_string())]
+    }
+
+    #[test]
+    fn ram_footprint_counts_slots_past_the_highest_index() {
+        let contents = program(
+            "push local 2\npop temp 7\npush that 0\npush static 3\npop static 3\npush static 0\n",
+        );
+        let footprint = ram_footprint(&parse_program(&contents));
+        assert_eq!(
+            footprint,
+            RamFootprint {
+                local: 3,
+                temp: 8,
+                that: 1,
+                statics: 2,
+                ..RamFootprint::default()
+            }
+        );
+    }
+
+    #[test]
+    fn defined_functions_span_up_to_the_next_function() {
+        let contents =
+            program("function Main.main 2\npush constant 1\nreturn\nfunction Main.f x\n");
+        let functions = defined_functions(&parse_program(&contents));
+        assert_eq!(
+            functions
+                .iter()
+                .map(|x| (x.name, x.locals, x.span.clone()))
+                .collect::<Vec<_>>(),
+            [("Main.main", 2, 0..3), ("Main.f", 0, 3..4)]
+        );
+    }
+
+    #[test]
+    fn unresolved_calls_skip_the_os_and_external_functions() {
+        let contents = vec![
+            (
+                "Main".to_string(),
+                "function Main.main 0\ncall Math.multiply 2\ncall Util.f 0\ncall Lib.g 0\n"
+                    .to_string(),
+            ),
+            (
+                "Util".to_string(),
+                "call Lib.g 0\ncall Util.f 0\n".to_string(),
+            ),
+        ];
+        assert_eq!(
+            unresolved_calls(&parse_program(&contents), &["Lib.g".to_string()]),
+            [("Util.f", vec!["Main", "Util"])]
+        );
+    }
+
+    #[test]
+    fn basic_blocks_start_at_labels_and_after_jumps() {
+        let contents = program(
+            "function Main.main 0\npush constant 1\nif-goto A\npush constant 2\nlabel A\n\
+             goto A\nfunction Main.f 0\nreturn\n",
+        );
+        let instructions = parse_program(&contents);
+        let starts = basic_block_starts(&instructions.iter().collect::<Vec<_>>());
+        assert_eq!(
+            starts,
+            [
+                Some(("Main.main", 0)),
+                None,
+                None,
+                Some(("Main.main", 1)),
+                Some(("Main.main", 2)),
+                None,
+                Some(("Main.f", 0)),
+                None,
+            ]
+        );
+    }
+
+    #[test]
+    fn function_docs_take_the_comments_directly_above() {
+        let docs = function_docs(
+            "// Adds the two\n//\n// arguments\nfunction Main.add 0\n// detached\n\n\
+             function Main.f 0\nfunction Main.g 0 // trailing\n",
+        );
+        assert_eq!(docs.len(), 1);
+        assert_eq!(docs["Main.add"], "Adds the two\n\narguments");
+    }
+}
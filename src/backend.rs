@@ -0,0 +1,763 @@
+use crate::{
+    frame_label, function_label, Command, Instruction, Options, ReturnLabelAllocator, Segment,
+};
+use std::fmt::Write;
+
+/// A code generator turning VM instructions into the assembly language of a target machine,
+/// with one method per family of VM instructions
+pub trait Backend {
+    /// Returns the symbols predefined by the target's assembler,
+    /// which generated labels must not define or jump to
+    fn reserved_symbols(&self) -> &[&str] {
+        &[]
+    }
+
+    /// Returns the code run before the translated program,
+    /// setting up the stack and jumping to the entry function's label,
+    /// or calling it with the given arguments if there are any
+    fn bootstrap(&self, entry: &str, args: &[u16], options: &Options) -> String;
+
+    /// Returns the code trapping the CPU in an infinite loop,
+    /// appended after the translated program
+    fn halt(&self, options: &Options) -> String;
+
+    /// Returns the code run instead of the bootstrap when the entry function is missing,
+    /// trapping the CPU before it reaches the translated program. Defaults to the halt loop
+    fn halting_bootstrap(&self, options: &Options) -> String {
+        self.halt(options)
+    }
+
+    /// Returns the code for the memory operation VM instructions
+    /// (push, pop)
+    fn generate_memop(
+        &self,
+        instruction: &Instruction,
+        options: &Options,
+    ) -> Result<String, String>;
+
+    /// Returns the code for the 2-operand arithmetic & logical VM instructions
+    /// (add, sub, or, and)
+    fn generate_2op(&self, instruction: &Instruction, options: &Options) -> Result<String, String>;
+
+    /// Returns the code for the 1-operand logical VM instructions
+    /// (not, neg)
+    fn generate_1op(&self, instruction: &Instruction, options: &Options) -> Result<String, String>;
+
+    /// Returns the code for the logical comparison VM instructions
+    /// (eq, gt, lt)
+    fn generate_cmp(&self, instruction: &Instruction, options: &Options) -> Result<String, String>;
+
+    /// Returns the code for the branching VM instructions
+    /// (label, goto, if-goto)
+    fn generate_branching(
+        &self,
+        instruction: &Instruction,
+        options: &Options,
+    ) -> Result<String, String>;
+
+    /// Returns the code for the extended dialect "assert" VM instruction
+    fn generate_assert(
+        &self,
+        instruction: &Instruction,
+        options: &Options,
+    ) -> Result<String, String>;
+
+    /// Returns the code for the functions VM instructions
+    /// (function, call, return)
+    fn generate_functions(
+        &self,
+        instruction: &Instruction,
+        options: &Options,
+        return_labels: &mut ReturnLabelAllocator,
+    ) -> Result<String, String>;
+}
+
+/// Register the return sequence keeps the end of the returning frame in
+const RETURN_FRAME_REGISTER: u16 = 13;
+/// Register the return sequence keeps the return address in
+const RETURN_ADDRESS_REGISTER: u16 = 14;
+
+/// Returns the registers the generated code uses as scratch space, and what uses them
+pub(crate) fn scratch_registers(options: &Options) -> [(u16, &'static str); 3] {
+    [
+        (RETURN_FRAME_REGISTER, "return"),
+        (RETURN_ADDRESS_REGISTER, "return"),
+        (options.pop_scratch, "pop"),
+    ]
+}
+
+/// Returns the symbol of the pointer segment's register of the given index,
+/// THIS or THAT unless the pointer segment is moved elsewhere
+fn pointer_symbol(index: u16, options: &Options) -> Result<String, String> {
+    let base = options.pointer_base;
+    if !(3..16383).contains(&base) {
+        Err(format!(
+            "pointer base must be between 3 and 16382, after SP, LCL and ARG, got '{}'",
+            base
+        ))?
+    }
+    if (5..=12).contains(&base) || (5..=12).contains(&(base + 1)) {
+        Err(format!(
+            "pointer base {} overlaps the temp segment (R5-R12)",
+            base
+        ))?
+    }
+    if let Some((register, user)) = scratch_registers(options)
+        .into_iter()
+        .find(|(x, _)| (base..=base + 1).contains(x))
+    {
+        Err(format!(
+            "pointer base {} overlaps R{}, which the {} sequence uses as scratch",
+            base, register, user
+        ))?
+    }
+    Ok(match (base, index) {
+        (3, 0) => "THIS".to_string(),
+        (3, _) => "THAT".to_string(),
+        (_, _) if base + index < 16 => format!("R{}", base + index),
+        (_, _) => (base + index).to_string(),
+    })
+}
+
+/// Returns the code of a call or return sequence saving or restoring THIS and THAT
+/// with them moved to the configured pointer segment
+fn move_pointers(code: String, options: &Options) -> Result<String, String> {
+    if options.pointer_base == Options::default().pointer_base {
+        return Ok(code);
+    }
+    let this = format!("@{}\n", pointer_symbol(0, options)?);
+    let that = format!("@{}\n", pointer_symbol(1, options)?);
+    Ok(code.replace("@THIS\n", &this).replace("@THAT\n", &that))
+}
+
+/// Explanations of the steps of the call template,
+/// each given before the index of the instruction starting the step
+const CALL_NOTES: [(usize, &str); 8] = [
+    (0, "push the return address"),
+    (6, "save the caller's LCL"),
+    (12, "save the caller's ARG"),
+    (18, "save the caller's THIS"),
+    (24, "save the caller's THAT"),
+    (30, "ARG = SP - n_args - 5, the first argument"),
+    (
+        36,
+        "LCL = SP, the callee's locals start at the top of the stack",
+    ),
+    (40, "jump to the callee, which returns to the label below"),
+];
+
+/// Explanations of the steps of the return template,
+/// each given before the index of the instruction starting the step
+const RETURN_NOTES: [(usize, &str); 8] = [
+    (
+        0,
+        "save the return address, 5 slots below LCL, as the return value may overwrite it",
+    ),
+    (
+        7,
+        "move the return value to the caller's stack top, where ARG points",
+    ),
+    (13, "SP = ARG + 1, popping the arguments"),
+    (17, "restore the caller's THAT, 1 slot below LCL"),
+    (25, "restore the caller's THIS"),
+    (31, "restore the caller's ARG"),
+    (37, "restore the caller's LCL"),
+    (43, "jump to the saved return address"),
+];
+
+/// Interleaves the code with comments, each inserted before the instruction of the given
+/// index, counting only the non-empty lines
+fn annotate(code: &str, notes: &[(usize, &str)]) -> String {
+    let mut annotated = String::new();
+    for (i, line) in code.lines().filter(|x| !x.is_empty()).enumerate() {
+        for (_, note) in notes.iter().filter(|(j, _)| *j == i) {
+            annotated += &format!("// {}\n", note);
+        }
+        annotated += line;
+        annotated += "\n";
+    }
+    annotated
+}
+
+/// Escapes the separators of generated labels in a user label by doubling them,
+/// so that a label like `ret.1` can't collide with the `$ret.1` return label
+/// of its function, nor a `$` in it with a frame separator
+fn escape_label(name: &str) -> String {
+    name.replace('$', "$$").replace('.', "..")
+}
+
+/// This represents a memmory operation type
+/// Push / Pop
+#[derive(Clone, Copy)]
+enum MemOpType {
+    Push,
+    Pop,
+}
+
+/// Returns the code preceded by a comment naming each template it was filled from,
+/// when tracing templates
+fn traced(code: String, templates: &[&str], options: &Options) -> String {
+    match options.trace_templates {
+        true => {
+            let mut traced = templates
+                .iter()
+                .map(|x| format!("// template: {}\n", x))
+                .collect::<String>();
+            traced += &code;
+            traced
+        }
+        false => code,
+    }
+}
+
+/// Return the formatted code for a general segment push/pop VM instruction
+/// (segments: argument, local, this, that), given the symbol of the segment's base,
+/// with the template it was filled from.
+/// When optimizing, index 0 addresses the base directly without adding the offset
+fn segment_fmt(
+    opt: MemOpType,
+    segment: &str,
+    index: u16,
+    options: &Options,
+) -> Result<(String, &'static str), String> {
+    let zero_offset = options.optimize && index == 0;
+    Ok(match opt {
+        MemOpType::Push if zero_offset => (
+            format!(
+                include_str!("./translations/push/segment_zero.asm"),
+                segment
+            ),
+            "push/segment_zero.asm",
+        ),
+        MemOpType::Push => (
+            format!(
+                include_str!("./translations/push/segment.asm"),
+                segment, index
+            ),
+            "push/segment.asm",
+        ),
+        MemOpType::Pop => {
+            if !(13..=15).contains(&options.pop_scratch) {
+                Err(format!(
+                    "pop scratch register must be R13, R14 or R15, got 'R{}'",
+                    options.pop_scratch
+                ))?
+            }
+            if zero_offset {
+                return Ok((
+                    format!(include_str!("./translations/pop/segment_zero.asm"), segment),
+                    "pop/segment_zero.asm",
+                ));
+            }
+            let scratch = format!("R{}", options.pop_scratch);
+            (
+                format!(
+                    include_str!("./translations/pop/segment_full.asm"),
+                    segment, index, scratch, scratch
+                ),
+                "pop/segment_full.asm",
+            )
+        }
+    })
+}
+
+/// Returns the code of a push constant VM instruction, the most common instruction,
+/// written straight into a buffer of the right size instead of through the templates
+fn push_constant(value: u16) -> String {
+    const PUSH: &str = include_str!("./translations/push/main.asm");
+    let mut code = String::with_capacity(PUSH_CONSTANT_LEN + PUSH.len());
+    code.push('@');
+    write!(code, "{}", value).unwrap();
+    code.push_str("\nD=A\n");
+    code.push_str(PUSH);
+    code
+}
+
+/// Length of the longest code of `push/constant.asm`, with a 5-digit value
+const PUSH_CONSTANT_LEN: usize = "@32767\nD=A\n".len();
+
+/// Return the formatted code for a push/pop VM instruction
+/// addressing the given symbol directly (segments: static, temp, pointer),
+/// with the template it was filled from
+fn direct_fmt(opt: MemOpType, symbol: &str) -> (String, &'static str) {
+    match opt {
+        MemOpType::Push => (
+            format!(include_str!("./translations/push/direct.asm"), symbol),
+            "push/direct.asm",
+        ),
+        MemOpType::Pop => (
+            format!(include_str!("./translations/pop/direct_full.asm"), symbol),
+            "pop/direct_full.asm",
+        ),
+    }
+}
+
+/// Size of the Hack stack, RAM 256 to 2047, which a function's locals are pushed onto
+const MAX_LOCALS: usize = 2048 - 256;
+
+/// The symbols predefined by the Hack assembler
+const HACK_PREDEFINED_SYMBOLS: [&str; 23] = [
+    "SP", "LCL", "ARG", "THIS", "THAT", "R0", "R1", "R2", "R3", "R4", "R5", "R6", "R7", "R8", "R9",
+    "R10", "R11", "R12", "R13", "R14", "R15", "SCREEN", "KBD",
+];
+
+/// The backend targeting the Hack computer, the default
+pub struct Hack;
+
+impl Backend for Hack {
+    fn reserved_symbols(&self) -> &[&str] {
+        &HACK_PREDEFINED_SYMBOLS
+    }
+
+    fn bootstrap(&self, entry: &str, args: &[u16], options: &Options) -> String {
+        if args.is_empty() {
+            return format!(include_str!("./translations/init.asm"), entry);
+        }
+        let pushes = args
+            .iter()
+            .map(|x| {
+                format!(include_str!("./translations/push/constant.asm"), x)
+                    + include_str!("./translations/push/main.asm")
+            })
+            .collect::<String>();
+        // the entry function returns to a loop, leaving its result at the bottom of the stack
+        let return_label = options.label_prefix.clone() + "bootstrap$ret";
+        let call = format!(
+            include_str!("./translations/functions/call.asm"),
+            return_label,
+            args.len() + 5,
+            entry,
+            return_label
+        );
+        format!(
+            "{}{}{}\n@{}\n0;JMP\n",
+            include_str!("./translations/init_call.asm"),
+            pushes,
+            call.trim_end(),
+            return_label
+        )
+    }
+
+    fn halt(&self, options: &Options) -> String {
+        let label = options.label_prefix.clone() + "END";
+        format!(include_str!("./translations/halt.asm"), label, label)
+    }
+
+    fn halting_bootstrap(&self, options: &Options) -> String {
+        // the stack is set up as usual so that the RAM looks like any translated program's
+        let label = options.label_prefix.clone() + "END";
+        format!(include_str!("./translations/init.asm"), label) + &self.halt(options)
+    }
+
+    /// Returns the Hack assembly representation of the VM "push" and "pop" instruction
+    fn generate_memop(
+        &self,
+        instruction: &Instruction,
+        options: &Options,
+    ) -> Result<String, String> {
+        let (opt, segment, index) = match instruction.command()? {
+            Command::Push { segment, index } => (MemOpType::Push, segment, index),
+            Command::Pop { segment, index } => (MemOpType::Pop, segment, index),
+            _ => Err(format!(
+                "Invalid memmory operation instruction '{}'",
+                instruction.operation
+            ))?,
+        };
+        let (code, template) = match segment {
+            Segment::Constant => {
+                let templates = ["push/constant.asm", "push/main.asm"];
+                return Ok(traced(push_constant(index), &templates, options));
+            }
+            Segment::Argument => segment_fmt(opt, "ARG", index, options)?,
+            Segment::Local => segment_fmt(opt, "LCL", index, options)?,
+            Segment::This => segment_fmt(opt, &pointer_symbol(0, options)?, index, options)?,
+            Segment::That => segment_fmt(opt, &pointer_symbol(1, options)?, index, options)?,
+            Segment::Static => direct_fmt(opt, &format!("{}.{}", instruction.file, index)),
+            Segment::Temp => direct_fmt(opt, &format!("R{}", index as usize + 5)),
+            Segment::Pointer => direct_fmt(opt, &pointer_symbol(index, options)?),
+        };
+        Ok(match opt {
+            MemOpType::Push => traced(
+                code + include_str!("./translations/push/main.asm"),
+                &[template, "push/main.asm"],
+                options,
+            ),
+            MemOpType::Pop => traced(code, &[template], options),
+        })
+    }
+
+    /// Return the Hack assembly representation of the 2-operand arithmetic & logical VM instructions
+    /// (add, sub, or, and)
+    fn generate_2op(&self, instruction: &Instruction, options: &Options) -> Result<String, String> {
+        let g = |x| {
+            let code = include_str!("./translations/2op/main.asm").to_string() + x + "\n";
+            Ok(traced(code, &["2op/main.asm"], options))
+        };
+        match instruction.command() {
+            Ok(Command::Add) => g("M=M+D"),
+            Ok(Command::Sub) => g("M=M-D"),
+            Ok(Command::Or) => g("M=M|D"),
+            Ok(Command::And) => g("M=M&D"),
+            _ => Err(format!(
+                "Invalid 2-operand arithemtic/logical instruction {}",
+                instruction.operation
+            )),
+        }
+    }
+
+    /// Return the Hack assembly representation of the 1-operand logical VM instructions
+    /// (not, neg)
+    fn generate_1op(
+        &self,
+        instruction: &Instruction,
+        _options: &Options,
+    ) -> Result<String, String> {
+        let g = |x| Ok("@SP\nA=M-1\n".to_string() + x + "\n");
+        match instruction.command() {
+            Ok(Command::Neg) => g("M=-M"),
+            Ok(Command::Not) => g("M=!M"),
+            _ => Err(format!(
+                "Invalid 1-operand logical instruction '{}'",
+                instruction.operation
+            )),
+        }
+    }
+
+    /// Return the Hack assembly representation of the logical comparison VM instructions
+    /// (eq, gt, lt)
+    fn generate_cmp(&self, instruction: &Instruction, options: &Options) -> Result<String, String> {
+        // labels must not start with a digit, and are qualified
+        // as comparisons of different files can have the same id
+        let label = frame_label(instruction, options) + "$cmp." + &instruction.label_id.to_string();
+        let g = |x| {
+            let code = format!(
+                include_str!("./translations/cmp/main.asm"),
+                label, x, label, label, label, label
+            );
+            Ok(traced(code, &["cmp/main.asm"], options))
+        };
+        match instruction.command() {
+            Ok(Command::Eq) => g("JEQ"),
+            Ok(Command::Gt) => g("JGT"),
+            Ok(Command::Lt) => g("JLT"),
+            _ => Err(format!(
+                "Invalid logical comparison instruction '{}'",
+                instruction.operation
+            )),
+        }
+    }
+
+    /// Returns the Hack assembly representation of the branching VM instructions
+    /// (label, goto, if-goto)
+    fn generate_branching(
+        &self,
+        instruction: &Instruction,
+        options: &Options,
+    ) -> Result<String, String> {
+        let l_name = |x| frame_label(instruction, options) + "$" + &escape_label(x);
+        Ok(match instruction.command()? {
+            Command::Label(x) => format!("({})\n", l_name(x)),
+            Command::Goto(x) => format!("@{}\n0;JMP\n", l_name(x)),
+            Command::IfGoto(x) => traced(
+                format!(
+                    include_str!("./translations/branching/if-goto.asm"),
+                    l_name(x)
+                ),
+                &["branching/if-goto.asm"],
+                options,
+            ),
+            _ => Err(format!(
+                "Invalid branching instruction '{}'",
+                instruction.operation
+            ))?,
+        })
+    }
+
+    /// Returns the Hack assembly representation of the extended dialect "assert" VM instruction,
+    /// which pops the top of the stack and, if it is false, writes a sentinel and halts
+    fn generate_assert(
+        &self,
+        instruction: &Instruction,
+        options: &Options,
+    ) -> Result<String, String> {
+        let label =
+            frame_label(instruction, options) + "$assert." + &instruction.label_id.to_string();
+        let code = format!(
+            include_str!("./translations/extended/assert.asm"),
+            label, label, label, options.assert_address, label, label, label
+        );
+        Ok(traced(code, &["extended/assert.asm"], options))
+    }
+
+    /// Returns the Hack assembly representation of the functions VM instructions
+    /// (function, call, return)
+    fn generate_functions(
+        &self,
+        instruction: &Instruction,
+        options: &Options,
+        return_labels: &mut ReturnLabelAllocator,
+    ) -> Result<String, String> {
+        Ok(match instruction.command()? {
+            Command::Function { name, n_vars } => {
+                if n_vars > MAX_LOCALS {
+                    Err(format!(
+                        "function declares {} locals, more than the {} words of the stack",
+                        n_vars, MAX_LOCALS
+                    ))?
+                }
+                let code = format!(
+                    include_str!("./translations/functions/function.asm"),
+                    function_label(name, Some(instruction.file), options),
+                    n_vars,
+                    "M=0\nA=A+1\n".repeat(n_vars)
+                );
+                traced(code, &["functions/function.asm"], options)
+            }
+            Command::Call { name, n_args } => {
+                let frame = match options.qualify_labels {
+                    true => frame_label(instruction, options),
+                    false => options.label_prefix.clone() + instruction.frame.unwrap_or("global"),
+                };
+                let return_label = match options.stable_labels {
+                    true => format!("{}$ret.{}", frame, instruction.label_id),
+                    false => return_labels.allocate(&frame),
+                };
+
+                let code = format!(
+                    include_str!("./translations/functions/call.asm"),
+                    return_label,
+                    // ARG skips back over the 5 saved frame slots and the arguments,
+                    // so with no arguments it points at the saved return address,
+                    // which return overwrites with the return value once saved
+                    n_args + 5,
+                    function_label(name, instruction.callee_file, options),
+                    return_label
+                );
+                let code = move_pointers(code, options)?;
+                let code = match options.annotate_frames {
+                    true => annotate(&code, &CALL_NOTES),
+                    false => code,
+                };
+                traced(code, &["functions/call.asm"], options)
+            }
+            Command::Return => {
+                let frame = format!("R{}", RETURN_FRAME_REGISTER);
+                let address = format!("R{}", RETURN_ADDRESS_REGISTER);
+                let code = format!(
+                    include_str!("./translations/functions/return.asm"),
+                    address, frame, frame, frame, frame, address
+                );
+                let code = move_pointers(code, options)?;
+                let code = match options.annotate_frames {
+                    true => annotate(&code, &RETURN_NOTES),
+                    false => code,
+                };
+                traced(code, &["functions/return.asm"], options)
+            }
+            _ => Err(format!(
+                "Invalid functions instruction '{}'",
+                instruction.operation
+            ))?,
+        })
+    }
+}
+
+/// A minimal backend emitting a placeholder comment for every instruction,
+/// to start a new backend from
+pub struct Stub;
+
+impl Stub {
+    /// Returns the placeholder for the instruction
+    fn unsupported(instruction: &Instruction) -> Result<String, String> {
+        Ok(format!("// unsupported: {}", instruction.operation))
+    }
+}
+
+impl Backend for Stub {
+    fn bootstrap(&self, _entry: &str, _args: &[u16], _options: &Options) -> String {
+        String::new()
+    }
+
+    fn halt(&self, _options: &Options) -> String {
+        String::new()
+    }
+
+    fn generate_memop(
+        &self,
+        instruction: &Instruction,
+        _options: &Options,
+    ) -> Result<String, String> {
+        Stub::unsupported(instruction)
+    }
+
+    fn generate_2op(
+        &self,
+        instruction: &Instruction,
+        _options: &Options,
+    ) -> Result<String, String> {
+        Stub::unsupported(instruction)
+    }
+
+    fn generate_1op(
+        &self,
+        instruction: &Instruction,
+        _options: &Options,
+    ) -> Result<String, String> {
+        Stub::unsupported(instruction)
+    }
+
+    fn generate_cmp(
+        &self,
+        instruction: &Instruction,
+        _options: &Options,
+    ) -> Result<String, String> {
+        Stub::unsupported(instruction)
+    }
+
+    fn generate_branching(
+        &self,
+        instruction: &Instruction,
+        _options: &Options,
+    ) -> Result<String, String> {
+        Stub::unsupported(instruction)
+    }
+
+    fn generate_assert(
+        &self,
+        instruction: &Instruction,
+        _options: &Options,
+    ) -> Result<String, String> {
+        Stub::unsupported(instruction)
+    }
+
+    fn generate_functions(
+        &self,
+        instruction: &Instruction,
+        _options: &Options,
+        _return_labels: &mut ReturnLabelAllocator,
+    ) -> Result<String, String> {
+        Stub::unsupported(instruction)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Stub;
+    use crate::{run_vm, translate, translate_with, Dialect, Options};
+
+    #[test]
+    fn call_with_zero_arguments() {
+        // the callee's ARG points at the caller's stack top, where the return value goes
+        let source =
+            "function Sys.init 0\npush constant 1\ncall Sys.seven 0\nlabel end\ngoto end\n\
+                      function Sys.seven 0\npush constant 7\nreturn\n";
+        let program = vec![("Sys".to_string(), source.to_string())];
+        let emulator = run_vm(program, &Options::default(), 10_000).unwrap();
+        assert!(emulator.halted());
+        assert_eq!(emulator.ram()[0], 263);
+        assert_eq!(emulator.ram()[261..263], [1, 7]);
+        assert_eq!(emulator.ram()[1..3], [261, 256]);
+    }
+
+    #[test]
+    fn failing_assert_writes_the_sentinel() {
+        let source = "function Sys.init 0\npush constant 0\nassert\nlabel end\ngoto end\n";
+        let program = vec![("Sys".to_string(), source.to_string())];
+        let options = Options::default()
+            .dialect(Dialect::Extended)
+            .assert_address(5000u16);
+        let code = translate(program.clone(), &options).unwrap();
+        assert!(code.contains("@Sys.Sys.init$assert.2.fail\nD;JEQ"));
+        assert!(code.contains("@5000\nM=-1"));
+        let emulator = run_vm(program, &options, 10_000).unwrap();
+        assert!(emulator.halted());
+        assert_eq!(emulator.ram()[5000], -1);
+    }
+
+    #[test]
+    fn pop_goes_through_the_chosen_scratch_register() {
+        let source = "function Sys.init 0\npush constant 1\npop local 2\n";
+        let program = vec![("Sys".to_string(), source.to_string())];
+        let code = translate(program.clone(), &Options::default().pop_scratch(15u16)).unwrap();
+        assert!(code.contains("@R15\nM=D") && !code.contains("@R13"));
+        let errors = translate(program, &Options::default().pop_scratch(7u16)).unwrap_err();
+        assert!(errors[0].ends_with("pop scratch register must be R13, R14 or R15, got 'R7'"));
+    }
+
+    #[test]
+    fn stub_backend_comments_each_instruction() {
+        let source = "function Sys.init 0\npush constant 1\npop local 0\nadd\nnot\n\
+                      lt\nlabel end\ngoto end\ncall Sys.init 0\nreturn\n";
+        let program = vec![("Sys".to_string(), source.to_string())];
+        let code = translate_with(&Stub, program, &Options::default()).unwrap();
+        let stubs: Vec<&str> = code
+            .lines()
+            .filter_map(|x| x.strip_prefix("// unsupported: "))
+            .collect();
+        assert_eq!(
+            stubs,
+            ["function", "push", "pop", "add", "not", "lt", "label", "goto", "call", "return"]
+        );
+    }
+
+    #[test]
+    fn user_labels_with_separators_are_escaped() {
+        let source = "function Sys.init 0\ncall Sys.f 0\nlabel my$label\nlabel ret.0\n\
+                      goto my$label\nfunction Sys.f 0\npush constant 0\nreturn\n";
+        let program = vec![("Sys".to_string(), source.to_string())];
+        let code = translate(program, &Options::default()).unwrap();
+        let labels: Vec<&str> = code
+            .lines()
+            .filter_map(|x| x.strip_prefix('(')?.strip_suffix(')'))
+            .collect();
+        assert!(labels.contains(&"Sys.init$ret.0"));
+        assert!(labels.contains(&"Sys.Sys.init$my$$label"));
+        assert!(labels.contains(&"Sys.Sys.init$ret..0"));
+        assert!(code.contains("@Sys.Sys.init$my$$label\n0;JMP"));
+        let mut unique = labels.clone();
+        unique.sort();
+        unique.dedup();
+        assert_eq!(unique.len(), labels.len());
+    }
+
+    #[test]
+    fn huge_local_counts_are_rejected() {
+        let translate_locals = |n: usize| {
+            let source = format!("function Sys.init {}\nreturn\n", n);
+            translate(vec![("Sys".to_string(), source)], &Options::default())
+        };
+        assert!(translate_locals(super::MAX_LOCALS).is_ok());
+        for n in [super::MAX_LOCALS + 1, 100_000] {
+            let errors = translate_locals(n).unwrap_err();
+            assert_eq!(errors.len(), 1);
+            assert!(errors[0].ends_with(&format!(
+                "function declares {} locals, more than the 1792 words of the stack",
+                n
+            )));
+        }
+    }
+
+    #[test]
+    fn pointer_base_keeps_clear_of_temp() {
+        let source = "function Sys.init 0\npush pointer 1\npop pointer 0\n";
+        let program = vec![("Sys".to_string(), source.to_string())];
+        let with_base =
+            |base: u16| translate(program.clone(), &Options::default().pointer_base(base));
+        for base in [4u16, 8, 12] {
+            let errors = with_base(base).unwrap_err();
+            assert!(
+                errors[0].ends_with(&format!(
+                    "pointer base {} overlaps the temp segment (R5-R12)",
+                    base
+                )),
+                "{:?}",
+                errors
+            );
+        }
+        assert!(with_base(3).unwrap().contains("@THAT\n"));
+        let moved = with_base(15).unwrap();
+        assert!(moved.contains("@16\n") && moved.contains("@R15\n"));
+        let far = with_base(1000).unwrap();
+        assert!(far.contains("@1001\n") && far.contains("@1000\n"));
+    }
+}
@@ -0,0 +1,134 @@
+use crate::{translate, Options, TranslateError};
+use std::fs;
+use std::path::Path;
+
+/// Returns the stem of the path as an owned string
+fn stem(path: &Path) -> Result<String, TranslateError> {
+    path.file_stem()
+        .and_then(|x| x.to_str())
+        .map(|x| x.to_string())
+        .ok_or(TranslateError::Io(format!(
+            "Invalid file name '{}'",
+            path.display()
+        )))
+}
+
+/// Reads the file at the path as a tuple of its stem and contents
+fn read_source(path: &Path) -> Result<(String, String), TranslateError> {
+    let contents = fs::read_to_string(path)
+        .map_err(|e| TranslateError::Io(format!("Unable to read '{}': {}", path.display(), e)))?;
+    Ok((stem(path)?, contents))
+}
+
+/// Translates the .vm file or directory of .vm files at `input` into the Hack assembly
+/// file at `output`. Unlike the command line tool this never panics, exits or prints,
+/// so it can be called from a `build.rs` script:
+///
+/// ```no_run
+/// use std::path::Path;
+/// use vmtranslator::{build_translate, Options};
+///
+/// build_translate(Path::new("vm/Main"), Path::new("asm/Main.asm"), Options::default())
+///     .expect("VM translation failed");
+/// ```
+pub fn build_translate(
+    input: &Path,
+    output: &Path,
+    opts: Options,
+) -> Result<(), Vec<TranslateError>> {
+    let contents = match input.is_dir() {
+        true => {
+            let mut paths = fs::read_dir(input)
+                .map_err(|e| {
+                    vec![TranslateError::Io(format!(
+                        "Unable to read '{}': {}",
+                        input.display(),
+                        e
+                    ))]
+                })?
+                .filter_map(|e| Some(e.ok()?.path()))
+                .filter(|p| p.is_file() && p.extension().is_some_and(|x| x == "vm"))
+                .collect::<Vec<_>>();
+            paths.sort();
+            paths
+                .iter()
+                .map(|p| read_source(p))
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(|e| vec![e])?
+        }
+        false => vec![read_source(input).map_err(|e| vec![e])?],
+    };
+    let code = translate(contents, &opts).map_err(|e| {
+        e.into_iter()
+            .map(TranslateError::Instruction)
+            .collect::<Vec<_>>()
+    })?;
+    fs::write(output, code).map_err(|e| {
+        vec![TranslateError::Io(format!(
+            "Unable to write '{}': {}",
+            output.display(),
+            e
+        ))]
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    /// Returns a new directory of the VM files under the system's temporary directory
+    fn vm_dir(name: &str, files: &[(&str, &str)]) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "vmtranslator-build-{}-{}",
+            name,
+            std::process::id()
+        ));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        for (file, source) in files {
+            fs::write(dir.join(file), source).unwrap();
+        }
+        dir
+    }
+
+    #[test]
+    fn translates_the_vm_files_of_the_directory() {
+        let main = "function Main.main 0\npush constant 0\nreturn\n";
+        let sys = "function Sys.init 0\ncall Main.main 0\n";
+        let dir = vm_dir(
+            "dir",
+            &[("Main.vm", main), ("Sys.vm", sys), ("notes.txt", "x")],
+        );
+        let output = dir.join("out.asm");
+        build_translate(&dir, &output, Options::default()).unwrap();
+        let contents = vec![
+            ("Main".to_string(), main.to_string()),
+            ("Sys".to_string(), sys.to_string()),
+        ];
+        assert_eq!(
+            fs::read_to_string(&output).unwrap(),
+            translate(contents, &Options::default()).unwrap()
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn reports_errors_instead_of_panicking() {
+        let dir = vm_dir("errors", &[("Main.vm", "pop constant 1\n")]);
+        let errors = build_translate(
+            &dir.join("Main.vm"),
+            &dir.join("Main.asm"),
+            Options::default(),
+        )
+        .unwrap_err();
+        assert!(matches!(&errors[..], [TranslateError::Instruction(_)]));
+        let missing = dir.join("Missing.vm");
+        let errors =
+            build_translate(&missing, &dir.join("Missing.asm"), Options::default()).unwrap_err();
+        assert!(
+            matches!(&errors[..], [TranslateError::Io(x)] if x.starts_with(&format!("Unable to read '{}'", missing.display())))
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,305 @@
+use crate::validate::is_symbol;
+use crate::Instruction;
+
+/// A memory segment of the VM
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Segment {
+    Constant,
+    Argument,
+    Local,
+    This,
+    That,
+    Static,
+    Temp,
+    Pointer,
+}
+
+impl Segment {
+    /// Returns the segment with the given VM name
+    pub(crate) fn from_name(name: &str) -> Option<Self> {
+        Some(match name {
+            "constant" => Segment::Constant,
+            "argument" => Segment::Argument,
+            "local" => Segment::Local,
+            "this" => Segment::This,
+            "that" => Segment::That,
+            "static" => Segment::Static,
+            "temp" => Segment::Temp,
+            "pointer" => Segment::Pointer,
+            _ => None?,
+        })
+    }
+
+    /// Returns the VM name of the segment
+    pub(crate) fn name(self) -> &'static str {
+        match self {
+            Segment::Constant => "constant",
+            Segment::Argument => "argument",
+            Segment::Local => "local",
+            Segment::This => "this",
+            Segment::That => "that",
+            Segment::Static => "static",
+            Segment::Temp => "temp",
+            Segment::Pointer => "pointer",
+        }
+    }
+
+    /// Returns the highest index the segment can be accessed with: the 8 temp registers,
+    /// the 2 pointers, and for the segments whose index is loaded with an A-instruction,
+    /// the largest constant one can hold
+    fn max_index(self) -> u16 {
+        match self {
+            Segment::Pointer => 1,
+            Segment::Temp => 7,
+            Segment::Static => u16::MAX,
+            _ => 32767,
+        }
+    }
+}
+
+/// Parses the index argument of a push/pop VM instruction accessing the segment,
+/// checking it is a number within the segment
+pub(crate) fn parse_index(segment: Segment, index: &str) -> Result<u16, String> {
+    let invalid = || match segment {
+        Segment::Pointer => format!("invalid pointer index '{}'", index),
+        _ => format!("invalid index '{}' for segment '{}'", index, segment.name()),
+    };
+    if index.is_empty() || !index.chars().all(|c| c.is_ascii_digit()) {
+        Err(invalid())?;
+    }
+    match index.parse::<u16>() {
+        Ok(a) if a <= segment.max_index() => Ok(a),
+        _ if segment == Segment::Pointer => Err(format!(
+            "pointer index must be 0 (THIS) or 1 (THAT), got {}",
+            index
+        )),
+        _ => Err(format!(
+            "index {} is out of range for segment '{}', at most {}",
+            index,
+            segment.name(),
+            segment.max_index()
+        )),
+    }
+}
+
+/// A VM instruction with its arguments parsed and validated for its operation
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Command<'a> {
+    Push { segment: Segment, index: u16 },
+    Pop { segment: Segment, index: u16 },
+    Add,
+    Sub,
+    And,
+    Or,
+    Neg,
+    Not,
+    Eq,
+    Gt,
+    Lt,
+    Label(&'a str),
+    Goto(&'a str),
+    IfGoto(&'a str),
+    Function { name: &'a str, n_vars: usize },
+    Call { name: &'a str, n_args: usize },
+    Return,
+    Assert,
+}
+
+/// Parses the segment and index arguments of a push/pop VM instruction
+fn parse_memop(instruction: &Instruction) -> Result<(Segment, u16), String> {
+    let name = instruction
+        .arg1
+        .ok_or(format!("{} missing segment", instruction.operation))?;
+    let index = instruction
+        .arg2
+        .ok_or(format!("{} {} missing index", instruction.operation, name))?;
+    let segment = match Segment::from_name(name) {
+        Some(Segment::Constant) if instruction.operation == "pop" => None,
+        s => s,
+    }
+    .ok_or(format!("Invalid segment argument '{}'", name))?;
+    Ok((segment, parse_index(segment, index)?))
+}
+
+/// Parses the name and count arguments of a function or call VM instruction
+fn parse_function<'a>(
+    instruction: &Instruction<'a>,
+    count: &str,
+    context: &str,
+) -> Result<(&'a str, usize), String> {
+    let missing_name = match instruction.operation {
+        "call" => "Missing function name argument to call",
+        _ => "Missing function name argument",
+    };
+    let name = instruction.arg1.ok_or(missing_name)?;
+    if !is_symbol(name) {
+        Err(format!(
+            "invalid function name '{}', expected letters, digits, '_', '.', '$' and ':', not starting with a digit",
+            name
+        ))?;
+    }
+    let n = instruction
+        .arg2
+        .ok_or(format!("Missing {} argument for {}", count, context))?;
+    let n = n.parse::<usize>().or(Err(match instruction.operation {
+        // kept as the call error has always been worded
+        "call" => format!("Invalid {} argument for {}, '{}", count, context, n),
+        _ => format!("Invalid {} argument for {}, '{}'", count, context, n),
+    }))?;
+    Ok((name, n))
+}
+
+impl<'a> Instruction<'a> {
+    /// Returns the instruction as a command of its operation with typed arguments,
+    /// or the error describing why its arguments are invalid for the operation.
+    /// Surplus arguments are not checked
+    pub fn command(&self) -> Result<Command<'a>, String> {
+        let label = || self.arg1.ok_or("Missing label name argument");
+        Ok(match self.operation {
+            "push" => {
+                let (segment, index) = parse_memop(self)?;
+                Command::Push { segment, index }
+            }
+            "pop" => {
+                let (segment, index) = parse_memop(self)?;
+                Command::Pop { segment, index }
+            }
+            "add" => Command::Add,
+            "sub" => Command::Sub,
+            "and" => Command::And,
+            "or" => Command::Or,
+            "neg" => Command::Neg,
+            "not" => Command::Not,
+            "eq" => Command::Eq,
+            "gt" => Command::Gt,
+            "lt" => Command::Lt,
+            "label" => Command::Label(label()?),
+            "goto" => Command::Goto(label()?),
+            "if-goto" => Command::IfGoto(label()?),
+            "function" => {
+                let (name, n_vars) = parse_function(self, "n_vars", "function")?;
+                Command::Function { name, n_vars }
+            }
+            "call" => {
+                let (name, n_args) = parse_function(self, "n_args", "function call")?;
+                Command::Call { name, n_args }
+            }
+            "return" => Command::Return,
+            "assert" => Command::Assert,
+            o => Err(format!("Invalid VM instruction '{}'", o))?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn command(source: &str) -> Result<Command<'_>, String> {
+        Instruction::new(source, 0, 1, "Main").unwrap().command()
+    }
+
+    #[test]
+    fn pointer_index_out_of_range() {
+        assert_eq!(
+            command("push pointer 2"),
+            Err("pointer index must be 0 (THIS) or 1 (THAT), got 2".to_string())
+        );
+        assert_eq!(
+            command("pop pointer 1"),
+            Ok(Command::Pop {
+                segment: Segment::Pointer,
+                index: 1
+            })
+        );
+    }
+
+    #[test]
+    fn single_token_instructions() {
+        let error = |message: &str| Err(message.to_string());
+        assert_eq!(command("label"), error("Missing label name argument"));
+        assert_eq!(command("goto"), error("Missing label name argument"));
+        assert_eq!(command("if-goto"), error("Missing label name argument"));
+        assert_eq!(
+            command("call"),
+            error("Missing function name argument to call")
+        );
+        assert_eq!(command("function"), error("Missing function name argument"));
+        assert_eq!(command("foo"), error("Invalid VM instruction 'foo'"));
+    }
+
+    #[test]
+    fn bad_indices_name_the_segment() {
+        for (segment, max) in [
+            ("constant", 32767),
+            ("argument", 32767),
+            ("local", 32767),
+            ("this", 32767),
+            ("that", 32767),
+            ("static", 65535),
+            ("temp", 7),
+        ] {
+            assert_eq!(
+                command(&format!("push {} x1", segment)),
+                Err(format!("invalid index 'x1' for segment '{}'", segment))
+            );
+            assert_eq!(
+                command(&format!("push {} 70000", segment)),
+                Err(format!(
+                    "index 70000 is out of range for segment '{}', at most {}",
+                    segment, max
+                ))
+            );
+            assert!(command(&format!("push {} {}", segment, max)).is_ok());
+        }
+        assert_eq!(
+            command("pop local -1"),
+            Err("invalid index '-1' for segment 'local'".to_string())
+        );
+    }
+
+    #[test]
+    fn pointer_index_not_a_number() {
+        assert_eq!(
+            command("push pointer x"),
+            Err("invalid pointer index 'x'".to_string())
+        );
+        assert_eq!(
+            command("pop pointer 70000"),
+            Err("pointer index must be 0 (THIS) or 1 (THAT), got 70000".to_string())
+        );
+    }
+
+    #[test]
+    fn indices_past_the_segment() {
+        assert_eq!(
+            command("push temp 8"),
+            Err("index 8 is out of range for segment 'temp', at most 7".to_string())
+        );
+        assert_eq!(
+            command("push constant 40000"),
+            Err("index 40000 is out of range for segment 'constant', at most 32767".to_string())
+        );
+        assert_eq!(
+            command("push temp 7"),
+            Ok(Command::Push {
+                segment: Segment::Temp,
+                index: 7
+            })
+        );
+    }
+
+    #[test]
+    fn missing_memop_arguments_are_named() {
+        assert_eq!(command("push"), Err("push missing segment".to_string()));
+        assert_eq!(command("pop"), Err("pop missing segment".to_string()));
+        assert_eq!(
+            command("push constant"),
+            Err("push constant missing index".to_string())
+        );
+        assert_eq!(
+            command("pop local"),
+            Err("pop local missing index".to_string())
+        );
+    }
+}
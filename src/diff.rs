@@ -0,0 +1,198 @@
+use crate::{generate_blocks, incremental, parse_program, Hack, Instruction, Options};
+
+/// A translated instruction compared between two versions of a program
+struct Block<'a> {
+    instruction: &'a Instruction<'a>,
+    code: String,
+}
+
+impl Block<'_> {
+    /// Describes the instruction the block was generated from
+    fn describe(&self) -> String {
+        format!(
+            "{}.vm:{} '{}'",
+            self.instruction.file, self.instruction.line, self.instruction.raw
+        )
+    }
+
+    /// Returns the code of the block with each line prefixed by the marker
+    fn marked(&self, marker: char) -> String {
+        self.code
+            .lines()
+            .map(|x| format!("{} {}", marker, x))
+            .collect::<Vec<String>>()
+            .join("\n")
+    }
+}
+
+/// Translates the program into its blocks, with stable labels so that
+/// unchanged instructions translate the same in both versions
+fn blocks<'a>(
+    instructions: &'a [Instruction<'a>],
+    options: &Options,
+) -> Result<Vec<Block<'a>>, Vec<String>> {
+    match generate_blocks(&Hack, instructions, options, None) {
+        (blocks, errors) if errors.is_empty() => Ok(blocks
+            .into_iter()
+            .map(|(instruction, code)| Block { instruction, code })
+            .collect()),
+        (_, errors) => Err(errors),
+    }
+}
+
+/// Pairs up the blocks of both versions translated from the same instruction
+/// of the same file, as the longest common subsequence of the two programs
+fn align(old: &[Block], new: &[Block]) -> Vec<(Option<usize>, Option<usize>)> {
+    let same = |i: usize, j: usize| {
+        old[i].instruction.file == new[j].instruction.file
+            && old[i].instruction.raw == new[j].instruction.raw
+    };
+    let mut lengths = vec![vec![0; new.len() + 1]; old.len() + 1];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            lengths[i][j] = match same(i, j) {
+                true => lengths[i + 1][j + 1] + 1,
+                false => lengths[i + 1][j].max(lengths[i][j + 1]),
+            };
+        }
+    }
+    let (mut i, mut j) = (0, 0);
+    let mut pairs = vec![];
+    while i < old.len() || j < new.len() {
+        if i < old.len() && j < new.len() && same(i, j) {
+            pairs.push((Some(i), Some(j)));
+            (i, j) = (i + 1, j + 1);
+        } else if j == new.len() || (i < old.len() && lengths[i + 1][j] >= lengths[i][j + 1]) {
+            pairs.push((Some(i), None));
+            i += 1;
+        } else {
+            pairs.push((None, Some(j)));
+            j += 1;
+        }
+    }
+    pairs
+}
+
+/// Translates both versions of a program and returns the differences between them
+/// block by block: the instructions removed, added or translated differently,
+/// each followed by its old and new code. A removed block directly followed by an
+/// added one is reported as a single changed block
+pub fn diff(
+    old: Vec<(String, String)>,
+    new: Vec<(String, String)>,
+    options: &Options,
+) -> Result<Vec<String>, Vec<String>> {
+    let options = Options {
+        stable_labels: true,
+        ..options.clone()
+    };
+    let mut old_instructions = parse_program(&old);
+    let mut new_instructions = parse_program(&new);
+    incremental::assign_stable_ids(&mut old_instructions);
+    incremental::assign_stable_ids(&mut new_instructions);
+    let old_blocks = blocks(&old_instructions, &options)?;
+    let new_blocks = blocks(&new_instructions, &options)?;
+    let pairs = align(&old_blocks, &new_blocks);
+    let mut differences = vec![];
+    let mut k = 0;
+    while k < pairs.len() {
+        match pairs[k] {
+            (Some(i), Some(j)) if old_blocks[i].code != new_blocks[j].code => {
+                let (o, n) = (&old_blocks[i], &new_blocks[j]);
+                differences.push(format!(
+                    "changed {}\n{}\n{}",
+                    n.describe(),
+                    o.marked('-'),
+                    n.marked('+')
+                ));
+            }
+            (Some(_), Some(_)) => (),
+            (Some(i), None) => match pairs.get(k + 1) {
+                Some((None, Some(j))) => {
+                    let (o, n) = (&old_blocks[i], &new_blocks[*j]);
+                    differences.push(format!(
+                        "changed {} -> {}\n{}\n{}",
+                        o.describe(),
+                        n.describe(),
+                        o.marked('-'),
+                        n.marked('+')
+                    ));
+                    k += 1;
+                }
+                _ => {
+                    let o = &old_blocks[i];
+                    differences.push(format!("removed {}\n{}", o.describe(), o.marked('-')));
+                }
+            },
+            (None, Some(j)) => {
+                let n = &new_blocks[j];
+                differences.push(format!("added {}\n{}", n.describe(), n.marked('+')));
+            }
+            (None, None) => (),
+        }
+        k += 1;
+    }
+    Ok(differences)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Returns the differences between the two versions of a file named Main
+    fn diff_main(old: &str, new: &str) -> Vec<String> {
+        let version = |source: &str| vec![("Main".to_string(), source.to_string())];
+        diff(version(old), version(new), &Options::default()).unwrap()
+    }
+
+    #[test]
+    fn unchanged_programs_have_no_differences() {
+        let source = "function Main.main 0\npush constant 1\neq\nreturn\n";
+        assert!(diff_main(source, source).is_empty());
+    }
+
+    #[test]
+    fn reports_added_removed_and_changed_blocks() {
+        let old = "function Main.main 0\npush constant 1\npop temp 0\nreturn\n";
+        let new = "function Main.main 0\npush constant 2\npop temp 0\nnot\nreturn\n";
+        let differences = diff_main(old, new);
+        let headers = differences
+            .iter()
+            .map(|x| x.lines().next().unwrap())
+            .collect::<Vec<&str>>();
+        assert_eq!(
+            headers,
+            [
+                "changed Main.vm:2 'push constant 1' -> Main.vm:2 'push constant 2'",
+                "added Main.vm:4 'not'",
+            ]
+        );
+        assert!(differences[1].lines().skip(1).all(|x| x.starts_with("+ ")));
+    }
+
+    #[test]
+    fn one_line_edit_changes_only_its_block() {
+        let old = "function Main.main 0\npush constant 1\npush constant 2\nlt\nif-goto A\n\
+                   push constant 7\npush constant 3\neq\nlabel A\ncall Main.main 0\nreturn\n";
+        let new = old.replace("push constant 7", "push constant 8");
+        let differences = diff_main(old, &new);
+        assert_eq!(differences.len(), 1);
+        assert!(differences[0]
+            .starts_with("changed Main.vm:6 'push constant 7' -> Main.vm:6 'push constant 8'\n"));
+        let translate = |source: &str| {
+            let program = vec![("Main".to_string(), source.to_string())];
+            crate::translate(program, &Options::default().stable_labels(true)).unwrap()
+        };
+        let (old, new) = (translate(old), translate(&new));
+        let changed = old
+            .lines()
+            .zip(new.lines())
+            .filter(|(a, b)| a != b)
+            .collect::<Vec<(&str, &str)>>();
+        assert_eq!(old.lines().count(), new.lines().count());
+        assert_eq!(
+            changed,
+            [("// push constant 7", "// push constant 8"), ("@7", "@8")]
+        );
+    }
+}
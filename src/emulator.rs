@@ -0,0 +1,364 @@
+use crate::{translate_with, validate_assembly, Hack, Options};
+use std::collections::HashMap;
+
+/// Words of the Hack RAM, including the screen and keyboard maps
+const RAM_SIZE: usize = 32768;
+
+/// Address of the first variable the assembler allocates
+const FIRST_VARIABLE: u16 = 16;
+
+/// The symbols predefined by the Hack assembler besides R0 to R15, with their addresses
+const PREDEFINED: [(&str, u16); 7] = [
+    ("SP", 0),
+    ("LCL", 1),
+    ("ARG", 2),
+    ("THIS", 3),
+    ("THAT", 4),
+    ("SCREEN", 16384),
+    ("KBD", 24576),
+];
+
+/// The ALU control bits (zx, nx, zy, ny, f, no) of each computation of x (D) and
+/// y (A or M), with the commutative ones spelled `D` first
+const ALU: [(&str, u8); 18] = [
+    ("0", 0b101010),
+    ("1", 0b111111),
+    ("-1", 0b111010),
+    ("D", 0b001100),
+    ("A", 0b110000),
+    ("!D", 0b001101),
+    ("!A", 0b110001),
+    ("-D", 0b001111),
+    ("-A", 0b110011),
+    ("D+1", 0b011111),
+    ("A+1", 0b110111),
+    ("D-1", 0b001110),
+    ("A-1", 0b110010),
+    ("D+A", 0b000010),
+    ("D-A", 0b010011),
+    ("A-D", 0b000111),
+    ("D&A", 0b000000),
+    ("D|A", 0b010101),
+];
+
+/// The jump conditions of the C-instruction, by their (lt, eq, gt) bits
+const JUMPS: [(&str, u8); 7] = [
+    ("JGT", 0b001),
+    ("JEQ", 0b010),
+    ("JGE", 0b011),
+    ("JLT", 0b100),
+    ("JNE", 0b101),
+    ("JLE", 0b110),
+    ("JMP", 0b111),
+];
+
+/// An assembled Hack instruction
+#[derive(Clone, Copy)]
+enum Op {
+    /// Loads the value into A
+    Address(u16),
+    /// Computes with the ALU, on M instead of A if `memory`,
+    /// storing the result into the destinations and jumping on the condition
+    Compute {
+        memory: bool,
+        alu: u8,
+        dest_a: bool,
+        dest_d: bool,
+        dest_m: bool,
+        jump: u8,
+    },
+}
+
+/// Returns the address of a predefined symbol, including the registers R0 to R15
+fn predefined(symbol: &str) -> Option<u16> {
+    PREDEFINED
+        .iter()
+        .find(|(name, _)| *name == symbol)
+        .map(|(_, address)| *address)
+        .or_else(|| {
+            symbol
+                .strip_prefix('R')
+                .and_then(|x| x.parse().ok())
+                .filter(|x| *x < 16)
+        })
+}
+
+/// Assembles a C-instruction already checked to be well-formed
+fn compute(line: &str) -> Op {
+    let (dest, rest) = line.split_once('=').unwrap_or(("", line));
+    let (comp, jump) = rest.split_once(';').unwrap_or((rest, ""));
+    let memory = comp.contains('M');
+    let comp = match comp.replace('M', "A").as_str() {
+        "A+D" => "D+A".to_string(),
+        "A&D" => "D&A".to_string(),
+        "A|D" => "D|A".to_string(),
+        x => x.to_string(),
+    };
+    Op::Compute {
+        memory,
+        alu: ALU.iter().find(|(x, _)| *x == comp).unwrap().1,
+        dest_a: dest.contains('A'),
+        dest_d: dest.contains('D'),
+        dest_m: dest.contains('M'),
+        jump: JUMPS
+            .iter()
+            .find(|(x, _)| *x == jump)
+            .map_or(0, |(_, j)| *j),
+    }
+}
+
+/// Returns the output of the Hack ALU for the control bits
+fn alu(x: i16, y: i16, bits: u8) -> i16 {
+    let bit = |n: u8| bits & (1 << (5 - n)) != 0;
+    let x = if bit(0) { 0 } else { x };
+    let x = if bit(1) { !x } else { x };
+    let y = if bit(2) { 0 } else { y };
+    let y = if bit(3) { !y } else { y };
+    let out = if bit(4) { x.wrapping_add(y) } else { x & y };
+    if bit(5) {
+        !out
+    } else {
+        out
+    }
+}
+
+/// A Hack CPU running an assembled program, to check what translated code does
+/// by the state it leaves the RAM in rather than by the text of the assembly:
+///
+/// ```
+/// use vmtranslator::Emulator;
+///
+/// let mut emulator = Emulator::new("@2\nD=A\n@3\nD=D+A\n@0\nM=D\n(END)\n@END\n0;JMP\n").unwrap();
+/// emulator.run(100);
+/// assert!(emulator.halted());
+/// assert_eq!(emulator.ram()[0], 5);
+/// ```
+pub struct Emulator {
+    rom: Vec<Op>,
+    ram: Vec<i16>,
+    pc: usize,
+    a: i16,
+    d: i16,
+}
+
+impl Emulator {
+    /// Assembles the Hack assembly into a new CPU with its RAM cleared,
+    /// or returns the errors of the malformed lines
+    pub fn new(code: &str) -> Result<Self, Vec<String>> {
+        validate_assembly(code)?;
+        let lines = code
+            .lines()
+            .map(|x| x.split("//").next().unwrap().trim())
+            .filter(|x| !x.is_empty())
+            .collect::<Vec<&str>>();
+        let mut symbols = HashMap::new();
+        let mut address = 0;
+        for line in &lines {
+            match line.strip_prefix('(') {
+                Some(label) => {
+                    symbols.insert(label.trim_end_matches(')'), address);
+                }
+                None => address += 1,
+            }
+        }
+        let mut next_variable = FIRST_VARIABLE;
+        let rom = lines
+            .iter()
+            .filter(|x| !x.starts_with('('))
+            .map(|line| match line.strip_prefix('@') {
+                Some(value) => Op::Address(match value.parse() {
+                    Ok(n) => n,
+                    Err(_) => match predefined(value) {
+                        Some(n) => n,
+                        None => *symbols.entry(value).or_insert_with(|| {
+                            next_variable += 1;
+                            next_variable - 1
+                        }),
+                    },
+                }),
+                None => compute(line),
+            })
+            .collect();
+        Ok(Emulator {
+            rom,
+            ram: vec![0; RAM_SIZE],
+            pc: 0,
+            a: 0,
+            d: 0,
+        })
+    }
+
+    /// Runs at most the given number of instructions, stopping early once the
+    /// program halts, and returns the number of instructions run
+    pub fn run(&mut self, cycles: usize) -> usize {
+        for i in 0..cycles {
+            if self.halted() {
+                return i;
+            }
+            self.step();
+        }
+        cycles
+    }
+
+    /// Runs the instruction at the program counter
+    fn step(&mut self) {
+        match self.rom[self.pc] {
+            Op::Address(value) => {
+                self.a = value as i16;
+                self.pc += 1;
+            }
+            Op::Compute {
+                memory,
+                alu: bits,
+                dest_a,
+                dest_d,
+                dest_m,
+                jump,
+            } => {
+                let address = self.a as u16 as usize % RAM_SIZE;
+                let y = if memory { self.ram[address] } else { self.a };
+                let out = alu(self.d, y, bits);
+                if dest_m {
+                    self.ram[address] = out;
+                }
+                if dest_d {
+                    self.d = out;
+                }
+                let target = self.a as u16 as usize;
+                if dest_a {
+                    self.a = out;
+                }
+                let condition = match out {
+                    x if x < 0 => 0b100,
+                    0 => 0b010,
+                    _ => 0b001,
+                };
+                self.pc = match jump & condition != 0 {
+                    true => target,
+                    false => self.pc + 1,
+                };
+            }
+        }
+    }
+
+    /// Returns whether the program has run past its last instruction or is
+    /// trapped in the `(L) @L 0;JMP` loop translated programs end with
+    pub fn halted(&self) -> bool {
+        let jumps_back = matches!(
+            self.rom.get(self.pc + 1),
+            Some(Op::Compute {
+                jump: 0b111,
+                dest_a: false,
+                ..
+            })
+        );
+        match self.rom.get(self.pc) {
+            Some(Op::Address(x)) => *x as usize == self.pc && jumps_back,
+            Some(_) => false,
+            None => true,
+        }
+    }
+
+    /// Returns the RAM, indexed by address
+    pub fn ram(&self) -> &[i16] {
+        &self.ram
+    }
+
+    /// Returns the value on top of the stack, if the stack pointer is past its base
+    pub fn top(&self) -> Option<i16> {
+        let sp = self.ram[0] as u16 as usize;
+        (257..=RAM_SIZE).contains(&sp).then(|| self.ram[sp - 1])
+    }
+}
+
+/// Translates the program with the Hack backend and runs it for at most the
+/// given number of instructions, returning the CPU it leaves behind:
+///
+/// ```
+/// use vmtranslator::{run_vm, Options};
+///
+/// let run = |source: &str| {
+///     let program = vec![("Sys".to_string(), source.to_string())];
+///     let emulator = run_vm(program, &Options::default(), 10000).unwrap();
+///     assert!(emulator.halted());
+///     emulator.top()
+/// };
+/// let end = "label END\ngoto END\n";
+/// let init = "function Sys.init 0\n";
+/// assert_eq!(run(&format!("{}push constant 2\npush constant 3\nadd\n{}", init, end)), Some(5));
+/// assert_eq!(run(&format!("{}push constant 2\npush constant 3\nlt\n{}", init, end)), Some(-1));
+/// assert_eq!(run(&format!("{}push constant 7\nneg\n{}", init, end)), Some(-7));
+/// let double = "function Sys.double 0\npush argument 0\npush argument 0\nadd\nreturn\n";
+/// let call = "push constant 21\ncall Sys.double 1\n";
+/// assert_eq!(run(&format!("{}{}{}{}", init, call, end, double)), Some(42));
+/// ```
+pub fn run_vm(
+    contents: Vec<(String, String)>,
+    options: &Options,
+    cycles: usize,
+) -> Result<Emulator, Vec<String>> {
+    let code = translate_with(&Hack, contents, options)?;
+    let mut emulator = Emulator::new(&code)?;
+    emulator.run(cycles);
+    Ok(emulator)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Runs the assembly until it halts, panicking if it doesn't within the cycles
+    fn run(code: &str, cycles: usize) -> Emulator {
+        let mut emulator = Emulator::new(code).unwrap();
+        emulator.run(cycles);
+        assert!(emulator.halted(), "not halted after {} cycles", cycles);
+        emulator
+    }
+
+    #[test]
+    fn computes_with_the_alu() {
+        let emulator = run(
+            "@5\nD=A\n@3\nD=D-A\n@R1\nM=D\nM=-M\nD=D|A\n@R2\nM=D\n@R2\nMD=!M\n@R3\nM=D+1\n",
+            100,
+        );
+        assert_eq!(emulator.ram()[1..4], [-2, -4, -3]);
+    }
+
+    #[test]
+    fn jumps_on_the_condition() {
+        // counts R0 down from 3, adding 1 to R1 each time round
+        let code = "@3\nD=A\n@R0\nM=D\n(LOOP)\n@R0\nD=M\n@DONE\nD;JEQ\n@R1\nM=M+1\n@R0\nM=M-1\n\
+                    @LOOP\n0;JMP\n(DONE)\n@DONE\n0;JMP\n";
+        let mut emulator = run(code, 1000);
+        assert_eq!(emulator.ram()[..2], [0, 3]);
+        assert_eq!(emulator.run(1000), 0);
+    }
+
+    #[test]
+    fn allocates_variables_from_16() {
+        let emulator = run("@x\nM=1\n@y\nM=-1\n@x\nD=M\n@SCREEN\nM=D\n", 100);
+        assert_eq!(emulator.ram()[16..18], [1, -1]);
+        assert_eq!(emulator.ram()[16384], 1);
+    }
+
+    #[test]
+    fn rejects_malformed_assembly() {
+        assert_eq!(
+            Emulator::new("@1\nD=Q\n").err(),
+            Some(vec![
+                "generated line 2: invalid computation 'Q' in 'D=Q'".to_string()
+            ])
+        );
+    }
+
+    #[test]
+    fn top_is_none_on_an_empty_stack() {
+        let emulator = run("@256\nD=A\n@SP\nM=D\n", 100);
+        assert_eq!(emulator.top(), None);
+        let emulator = run(
+            "@256\nD=A\n@SP\nM=D\n@9\nD=A\n@SP\nAM=M+1\nA=A-1\nM=D\n",
+            100,
+        );
+        assert_eq!(emulator.top(), Some(9));
+    }
+}
@@ -0,0 +1,152 @@
+use crate::{
+    check_duplicate_files, defining_file, function_label, generate_blocks, parse_program, Backend,
+    Options,
+};
+
+/// The start of the page, up to the rows of the table
+const HEADER: &str = r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<title>VM translation</title>
+<style>
+table { border-collapse: collapse; }
+td { border-top: 1px solid #ccc; padding: 0 1em; vertical-align: top; }
+pre { margin: 0.25em 0; }
+.file { color: #888; }
+.vm-op { color: #00c; font-weight: bold; }
+.vm-arg { color: #080; }
+.asm-comment { color: #888; }
+.asm-label { color: #a0a; }
+.asm-a { color: #c50; }
+</style>
+</head>
+<body>
+<table>
+<tr><th>Source</th><th>VM</th><th>Assembly</th></tr>
+"#;
+
+/// The end of the page, after the rows of the table
+const FOOTER: &str = "</table>\n</body>\n</html>\n";
+
+/// Escapes the characters of the text that are special in HTML
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Returns the VM instruction with its operation and arguments marked up for highlighting
+fn highlight_vm(raw: &str) -> String {
+    raw.split_whitespace()
+        .enumerate()
+        .map(|(i, x)| {
+            let class = if i == 0 { "vm-op" } else { "vm-arg" };
+            format!("<span class=\"{}\">{}</span>", class, escape(x))
+        })
+        .collect::<Vec<String>>()
+        .join(" ")
+}
+
+/// Returns the assembly with each line marked up for highlighting by its kind
+fn highlight_asm(code: &str) -> String {
+    code.lines()
+        .map(|x| {
+            let line = x.trim();
+            let class = if line.starts_with("//") {
+                "asm-comment"
+            } else if line.starts_with('(') {
+                "asm-label"
+            } else if line.starts_with('@') {
+                "asm-a"
+            } else {
+                "asm-c"
+            };
+            format!("<span class=\"{}\">{}</span>", class, escape(x))
+        })
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+/// Returns a row of the table, pairing the source with its assembly
+fn row(source: &str, file: &str, code: &str) -> String {
+    format!(
+        "<tr><td class=\"file\">{}</td><td class=\"vm\"><pre>{}</pre></td><td class=\"asm\"><pre>{}</pre></td></tr>\n",
+        escape(file),
+        source,
+        highlight_asm(code)
+    )
+}
+
+/// Translates the program into an HTML page showing each VM instruction
+/// next to the assembly generated for it, preceded by the bootstrap
+pub fn translate_html(
+    backend: &dyn Backend,
+    contents: Vec<(String, String)>,
+    options: &Options,
+) -> Result<String, Vec<String>> {
+    check_duplicate_files(&contents)?;
+    let instructions = parse_program(&contents);
+    let (blocks, errors) = generate_blocks(backend, &instructions, options, None);
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+    let entry = function_label(
+        &options.entry,
+        defining_file(&options.entry, &instructions),
+        options,
+    );
+    let bootstrap = backend.bootstrap(&entry, &options.entry_args, options);
+    let mut html = String::from(HEADER);
+    html += &row("<em>bootstrap</em>", "", &bootstrap);
+    for (x, code) in blocks {
+        html += &row(
+            &highlight_vm(x.raw),
+            &format!("{}.vm:{}", x.file, x.line),
+            &code,
+        );
+    }
+    Ok(html + FOOTER)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Hack, Stub};
+
+    /// Returns the page of the single file named Main
+    fn page(backend: &dyn Backend, source: &str) -> Result<String, Vec<String>> {
+        let contents = vec![("Main".to_string(), source.to_string())];
+        translate_html(backend, contents, &Options::default())
+    }
+
+    #[test]
+    fn rows_follow_the_instructions() {
+        let html = page(&Hack, "function Main.main 0\npush constant 1\n").unwrap();
+        assert!(html.starts_with(HEADER) && html.ends_with(FOOTER));
+        assert_eq!(html.matches("<tr><td").count(), 3);
+        assert!(html.contains(
+            "<span class=\"vm-op\">push</span> <span class=\"vm-arg\">constant</span> <span class=\"vm-arg\">1</span>"
+        ));
+        assert!(html.contains("Main.vm:2"));
+    }
+
+    #[test]
+    fn escapes_the_source() {
+        assert_eq!(
+            escape("<a href=\"x\">&</a>"),
+            "&lt;a href=&quot;x&quot;&gt;&amp;&lt;/a&gt;"
+        );
+        let html = page(&Stub, "label a<b\n").unwrap();
+        assert!(html.contains("a&lt;b") && !html.contains("a<b"));
+    }
+
+    #[test]
+    fn reports_the_translation_errors() {
+        assert_eq!(
+            page(&Hack, "pop constant 1\n").err().map(|x| x.len()),
+            Some(1)
+        );
+    }
+}
@@ -0,0 +1,231 @@
+use crate::{translate_blocks, Hack, Instruction, Options};
+use std::collections::{HashMap, HashSet};
+
+/// Returns the 64-bit FNV-1a hash of the parts, each followed by a separator
+fn fnv1a(parts: &[&str]) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for byte in parts.iter().flat_map(|x| x.bytes().chain([0])) {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash
+}
+
+/// Replaces the positional label ids of the instructions with ids hashed from
+/// their file, frame, text and the text of the instructions around them, so
+/// inserting or removing a line only changes the ids next to it. Instructions
+/// hashing the same are told apart by how many of them precede in the program,
+/// and colliding ids are resolved by taking the next free id
+pub(crate) fn assign_stable_ids(instructions: &mut [Instruction]) {
+    let neighbour = |i: Option<usize>, file: &str| {
+        i.and_then(|i| instructions.get(i))
+            .filter(|x| x.file == file)
+            .map_or("", |x| x.raw)
+    };
+    let hashes = instructions
+        .iter()
+        .enumerate()
+        .map(|(i, x)| {
+            [
+                x.file,
+                x.frame.unwrap_or_default(),
+                neighbour(i.checked_sub(1), x.file),
+                x.raw,
+                neighbour(Some(i + 1), x.file),
+            ]
+        })
+        .scan(HashMap::new(), |occurrences, parts| {
+            let occurrence = occurrences.entry(parts).or_insert(0);
+            *occurrence += 1;
+            Some(fnv1a(
+                &[&parts[..], &[occurrence.to_string().as_str()]].concat(),
+            ))
+        })
+        .collect::<Vec<u64>>();
+    let mut used = HashSet::new();
+    for (x, hash) in instructions.iter_mut().zip(hashes) {
+        let mut id = (hash % 1_000_000) as usize;
+        while !used.insert(id) {
+            id = (id + 1) % 1_000_000;
+        }
+        x.label_id = id;
+    }
+}
+
+/// Everything the translation of a single instruction depends on
+#[derive(Clone, PartialEq, Eq, Hash)]
+struct BlockKey {
+    file: String,
+    frame: Option<String>,
+    raw: String,
+    label_id: usize,
+    callee_file: Option<String>,
+    /// Only set when line directives are emitted
+    line: Option<usize>,
+}
+
+impl BlockKey {
+    fn new(instruction: &Instruction, options: &Options) -> Self {
+        BlockKey {
+            file: instruction.file.to_string(),
+            frame: instruction.frame.map(str::to_string),
+            raw: instruction.raw.to_string(),
+            label_id: instruction.label_id,
+            callee_file: instruction.callee_file.map(str::to_string),
+            line: options.include_line_directives.then_some(instruction.line),
+        }
+    }
+}
+
+/// The blocks generated by previous translations of a program,
+/// reused by [`translate_incremental`] for the instructions that did not change
+#[derive(Default)]
+pub struct TranslationCache {
+    /// The hash of the options the cached blocks were generated with
+    options: u64,
+    /// The blocks of the last translation
+    blocks: HashMap<BlockKey, String>,
+    /// The blocks of the translation before it, not yet reused by the current one
+    previous: HashMap<BlockKey, String>,
+    /// Number of blocks generated rather than reused by the last translation
+    regenerated: usize,
+}
+
+impl TranslationCache {
+    /// Returns how many blocks the last translation had to generate
+    /// because they were not in the cache
+    pub fn regenerated(&self) -> usize {
+        self.regenerated
+    }
+
+    /// Returns the cached block of the instruction, generating and caching it if missing
+    pub(crate) fn block(
+        &mut self,
+        instruction: &Instruction,
+        options: &Options,
+        generate: impl FnOnce() -> Result<String, String>,
+    ) -> Result<String, String> {
+        let key = BlockKey::new(instruction, options);
+        let code = match self.previous.remove(&key) {
+            Some(code) => code,
+            None => {
+                self.regenerated += 1;
+                generate()?
+            }
+        };
+        self.blocks.insert(key, code.clone());
+        Ok(code)
+    }
+}
+
+/// Returns the hash of everything in the options the output depends on: their flags,
+/// which only name the custom opcodes, and the contents the flags don't show
+fn options_hash(options: &Options) -> u64 {
+    let mut templates = options.opcode_table.iter().collect::<Vec<_>>();
+    templates.sort();
+    let flags = options.flags();
+    let parts = flags
+        .iter()
+        .map(String::as_str)
+        .chain(
+            templates
+                .iter()
+                .flat_map(|(name, code)| [name.as_str(), code.as_str()]),
+        )
+        .chain([options.asm_header.as_str(), options.asm_footer.as_str()])
+        .collect::<Vec<&str>>();
+    fnv1a(&parts)
+}
+
+/// Translates the program like [`crate::translate`] with stable labels,
+/// reusing the blocks cached from previous calls for unchanged instructions
+/// so that a small edit only regenerates and changes the blocks around it
+pub fn translate_incremental(
+    contents: Vec<(String, String)>,
+    options: &Options,
+    cache: &mut TranslationCache,
+) -> Result<String, Vec<String>> {
+    let options = Options {
+        stable_labels: true,
+        ..options.clone()
+    };
+    cache.previous = std::mem::take(&mut cache.blocks);
+    if cache.options != options_hash(&options) {
+        cache.options = options_hash(&options);
+        cache.previous.clear();
+    }
+    cache.regenerated = 0;
+    translate_blocks(&Hack, contents, &options, Some(cache), None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::translate;
+
+    /// Returns the options with the template of the `myop` custom opcode
+    fn with_myop(template: &str) -> Options {
+        let mut table = HashMap::new();
+        table.insert("myop".to_string(), template.to_string());
+        Options::default().opcode_table(table)
+    }
+
+    fn program() -> Vec<(String, String)> {
+        let source = "function Sys.init 0\nmyop\npush constant 1\nlabel end\ngoto end\n";
+        vec![("Sys".to_string(), source.to_string())]
+    }
+
+    #[test]
+    fn unchanged_program_reuses_every_block() {
+        let mut cache = TranslationCache::default();
+        let first = translate_incremental(program(), &with_myop("@111"), &mut cache).unwrap();
+        let second = translate_incremental(program(), &with_myop("@111"), &mut cache).unwrap();
+        assert_eq!(first, second);
+        assert_eq!(cache.regenerated(), 0);
+    }
+
+    #[test]
+    fn changed_template_misses_the_cache() {
+        let mut cache = TranslationCache::default();
+        translate_incremental(program(), &with_myop("@111"), &mut cache).unwrap();
+        let output = translate_incremental(program(), &with_myop("@222"), &mut cache).unwrap();
+        assert!(cache.regenerated() > 0);
+        assert!(output.contains("@222") && !output.contains("@111"));
+        let fresh = translate(program(), &with_myop("@222").stable_labels(true)).unwrap();
+        assert_eq!(output, fresh);
+    }
+
+    #[test]
+    fn changed_header_misses_the_cache() {
+        let mut cache = TranslationCache::default();
+        let options = with_myop("@111");
+        translate_incremental(program(), &options, &mut cache).unwrap();
+        let options = options.asm_header("(TRAP)\n@TRAP\n0;JMP\n");
+        let output = translate_incremental(program(), &options, &mut cache).unwrap();
+        assert!(cache.regenerated() > 0);
+        assert!(output.contains("(TRAP)"));
+    }
+
+    #[test]
+    fn early_insertion_keeps_later_labels() {
+        let labels = |source: &str| {
+            let program = vec![("Main".to_string(), source.to_string())];
+            let output = translate(program, &Options::default().stable_labels(true)).unwrap();
+            output
+                .lines()
+                .filter(|x| x.starts_with('('))
+                .map(str::to_string)
+                .collect::<Vec<String>>()
+        };
+        let body = "push constant 1\npush constant 2\nlt\npop temp 0\npush constant 3\n\
+                    push constant 3\neq\ncall Main.f 0\nreturn\nfunction Main.f 0\n\
+                    push constant 0\nreturn\n";
+        let old = labels(&format!("function Main.main 0\n{}", body));
+        let new = labels(&format!(
+            "function Main.main 0\npush constant 9\npop temp 1\n{}",
+            body
+        ));
+        assert!(old.iter().any(|x| x.contains("$ret.")));
+        assert_eq!(new, old);
+    }
+}
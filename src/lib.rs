@@ -0,0 +1,2102 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+/// Passes a diagnostic of the given level to the installed logger,
+/// compiled out unless the `log` feature is enabled
+#[cfg(feature = "log")]
+macro_rules! log {
+    ($level:ident, $($arg:tt)*) => {
+        $crate::log::emit($crate::log::Level::$level, &format!($($arg)*))
+    };
+}
+
+#[cfg(not(feature = "log"))]
+macro_rules! log {
+    ($level:ident, $($arg:tt)*) => {
+        if false {
+            let _ = format!($($arg)*);
+        }
+    };
+}
+
+mod analysis;
+mod backend;
+#[cfg(not(target_arch = "wasm32"))]
+mod build;
+mod command;
+mod diff;
+mod emulator;
+mod html;
+mod incremental;
+mod link;
+mod lint;
+#[cfg(feature = "log")]
+mod log;
+mod opcodes;
+mod optimize;
+mod repl;
+mod validate;
+#[cfg(target_arch = "wasm32")]
+mod wasm;
+
+pub use analysis::{
+    defined_functions, function_docs, ram_footprint, unresolved_calls, FunctionInfo, RamFootprint,
+};
+pub use backend::{Backend, Hack, Stub};
+#[cfg(not(target_arch = "wasm32"))]
+pub use build::build_translate;
+pub use command::{Command, Segment};
+pub use diff::diff;
+pub use emulator::{run_vm, Emulator};
+pub use html::translate_html;
+pub use incremental::{translate_incremental, TranslationCache};
+pub use lint::lint;
+#[cfg(feature = "log")]
+pub use log::{set_logger, Level};
+pub use opcodes::parse_opcode_table;
+pub use repl::{translate_line, TranslateContext};
+pub use validate::validate_assembly;
+#[cfg(target_arch = "wasm32")]
+pub use wasm::translate_wasm;
+
+/// A VM instruction is represented here
+#[derive(Clone)]
+pub struct Instruction<'a> {
+    pub operation: &'a str,
+    pub arg1: Option<&'a str>,
+    pub arg2: Option<&'a str>,
+    pub extra: Vec<&'a str>,
+    /// The text of the instruction, cut at its `//` comment and trimmed
+    pub raw: &'a str,
+    pub file: &'a str,
+    pub id: usize,
+    /// Number making the labels generated for this instruction unique,
+    /// the id unless stable labels are requested
+    pub label_id: usize,
+    pub line: usize,
+    /// 1-based column of the instruction within its line
+    pub column: usize,
+    pub frame: Option<&'a str>,
+    pub callee_file: Option<&'a str>,
+}
+
+impl<'a> Instruction<'a> {
+    /// Given an instruction string (with whitespaces and comments removed)
+    /// and the line it was found on, returns a new Instruction
+    pub fn new(s: &'a str, id: usize, line: usize, file: &'a str) -> Result<Self, &'static str> {
+        let mut parts = s.split_whitespace();
+        Ok(Self {
+            raw: s,
+            operation: parts.next().ok_or("Unable to parse empty line")?,
+            arg1: parts.next(),
+            arg2: parts.next(),
+            extra: parts.collect(),
+            file,
+            id,
+            label_id: id,
+            line,
+            column: 1,
+            frame: None,
+            callee_file: None,
+        })
+    }
+
+    /// Returns the 1-based columns of the tokens of the instruction within its line
+    fn token_columns(&self) -> impl Iterator<Item = (usize, &'a str)> + '_ {
+        self.raw.split_whitespace().map(|x| {
+            let offset = x.as_ptr() as usize - self.raw.as_ptr() as usize;
+            (self.column + offset, x)
+        })
+    }
+
+    /// Returns the column of the token an error message is about: the first
+    /// token the message quotes, or the start of the instruction if it quotes none
+    fn error_column(&self, message: &str) -> usize {
+        message
+            .split('\'')
+            .skip(1)
+            .step_by(2)
+            .find_map(|quoted| self.token_columns().find(|(_, x)| *x == quoted))
+            .map_or(self.column, |(column, _)| column)
+    }
+
+    /// Returns the number of arguments given to the instruction
+    fn arg_count(&self) -> usize {
+        self.arg1.iter().chain(self.arg2.iter()).count() + self.extra.len()
+    }
+
+    /// Given a vector of instructions, set the frame field of self
+    fn set_frame(&mut self, instructions: &[Instruction<'a>]) {
+        self.frame = match self.operation {
+            "function" => self.arg1,
+            _ => instructions
+                .iter()
+                .filter(|x| x.operation == "function")
+                .filter(|x| x.file == self.file)
+                .rfind(|x| x.id < self.id)
+                .and_then(|x| x.arg1),
+        };
+    }
+
+    /// Given a vector of instructions, set the callee_file field of self
+    /// to the file defining the function this instruction calls
+    fn set_callee_file(&mut self, instructions: &[Instruction<'a>]) {
+        if self.operation == "call" {
+            self.callee_file = defining_file(self.arg1.unwrap_or_default(), instructions);
+        }
+    }
+}
+
+/// Removes the byte-order mark some editors start UTF-8 files with
+fn strip_bom(contents: &str) -> &str {
+    contents.strip_prefix('\u{feff}').unwrap_or(contents)
+}
+
+/// Parses the program contents into a vector of instructions
+/// with whitespaces and comments removed, paired with their 1-based line number
+pub fn parse_contents(contents: &str) -> Vec<(usize, &str)> {
+    strip_bom(contents)
+        .lines()
+        .map(|x| x.split("//").next().unwrap().trim())
+        .enumerate()
+        .map(|(i, x)| (i + 1, x))
+        .filter(|(_, x)| !(x.is_empty()))
+        .collect()
+}
+
+/// Lazily parses the source of a VM file into its instructions, with their
+/// line and column but without their frame or callee file, which depend on the whole program
+pub fn parse<'a>(
+    file: &'a str,
+    source: &'a str,
+) -> impl Iterator<Item = Result<Instruction<'a>, TranslateError>> {
+    strip_bom(source)
+        .lines()
+        .enumerate()
+        .map(|(i, x)| (i + 1, x, x.split("//").next().unwrap().trim()))
+        .filter(|(_, _, x)| !x.is_empty())
+        .enumerate()
+        .map(move |(id, (line, source_line, x))| {
+            let mut instruction = Instruction::new(x, id, line, file)
+                .map_err(|e| TranslateError::Instruction(format!("{}.vm:{}: {}", file, line, e)))?;
+            instruction.column = source_line.len() - source_line.trim_start().len() + 1;
+            Ok(instruction)
+        })
+}
+
+/// Returns the program contents in canonical form: one instruction per line,
+/// its words separated by single spaces, without comments or empty lines
+pub fn normalize(contents: &str) -> String {
+    parse_contents(contents)
+        .iter()
+        .map(|(_, x)| x.split_whitespace().collect::<Vec<&str>>().join(" ") + "\n")
+        .collect()
+}
+
+/// Extracts the VM code embedded in a text file between `<!--vm` and `vm-->`
+/// markers, concatenating multiple blocks in the order they appear
+pub fn extract_markers(contents: &str) -> Result<String, String> {
+    let mut blocks = vec![];
+    let mut rest = contents;
+    while let Some(start) = rest.find("<!--vm") {
+        let block = &rest[start + "<!--vm".len()..];
+        let end = block
+            .find("vm-->")
+            .ok_or("Unterminated '<!--vm' block, expected a closing 'vm-->'")?;
+        blocks.push(&block[..end]);
+        rest = &block[end + "vm-->".len()..];
+    }
+    Ok(blocks.join("\n"))
+}
+
+/// An error stopping a translation
+#[derive(Clone, Debug, PartialEq)]
+pub enum TranslateError {
+    /// Reading the input or writing the output failed
+    Io(String),
+    /// An instruction couldn't be translated
+    Instruction(String),
+}
+
+impl fmt::Display for TranslateError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TranslateError::Io(e) | TranslateError::Instruction(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+/// The VM language dialect accepted by the translator
+#[derive(Clone, Copy, Default, PartialEq)]
+pub enum Dialect {
+    /// The standard VM language
+    #[default]
+    Standard,
+    /// The standard VM language plus the `assert` opcode
+    Extended,
+}
+
+/// What the translator does with an instruction whose opcode it doesn't know
+#[derive(Clone, Copy, Default, PartialEq)]
+pub enum UnknownOpcode {
+    /// Report it as an error
+    #[default]
+    Error,
+    /// Leave it out of the output
+    Skip,
+    /// Replace it with a `// unknown: <instruction>` comment
+    Comment,
+}
+
+/// What the translator does when no input file defines the entry function
+#[derive(Clone, Copy, Default, PartialEq)]
+pub enum MissingEntry {
+    /// Report it as an error, when translating more than one file
+    #[default]
+    Error,
+    /// Replace the bootstrap with one setting up the stack and trapping the CPU
+    Halt,
+}
+
+/// The user-selectable translation options
+#[derive(Clone)]
+pub struct Options {
+    /// Prefix every label, including function entry labels, with its file stem
+    pub qualify_labels: bool,
+    /// Omit the code of functions that are never called starting from the entry function
+    pub strip_unused_functions: bool,
+    /// Precede each block with a `// #line <n> <file>` source mapping directive
+    pub include_line_directives: bool,
+    /// Annotate each block with the net change in stack height it causes
+    pub trace: bool,
+    /// The VM language dialect to accept
+    pub dialect: Dialect,
+    /// What to do with instructions of unknown opcodes
+    pub unknown_opcode: UnknownOpcode,
+    /// What to do when the entry function isn't defined
+    pub missing_entry: MissingEntry,
+    /// RAM address a failing `assert` writes its sentinel (-1) to
+    pub assert_address: u16,
+    /// Static indices above this are reported by the linter as likely typos
+    pub static_threshold: u16,
+    /// Number of locals a function may declare beyond the ones it accesses
+    /// before the linter reports them as likely a miscount
+    pub local_slack: usize,
+    /// Number of the register (13 to 15) holding the target address while popping
+    /// into the argument, local, this and that segments
+    pub pop_scratch: u16,
+    /// RAM address of the pointer segment, holding the bases of this (pointer 0)
+    /// and that (pointer 1), 3 being THIS and THAT
+    pub pointer_base: u16,
+    /// Start the output with a comment block describing the translation unit
+    pub banner: bool,
+    /// End the output with an infinite loop trapping the CPU
+    pub emit_halt: bool,
+    /// Mark the bootstrap and the start of each file and function with comments
+    pub sections: bool,
+    /// Optimize the generated code
+    pub optimize: bool,
+    /// Annotate each block with its instruction count, measured before optimization,
+    /// and end the output with the total count
+    pub profile: bool,
+    /// Number generated labels with a hash of the instruction, its frame and its
+    /// neighbours instead of its position, so that editing a file leaves most labels unchanged
+    pub stable_labels: bool,
+    /// Templates of custom opcodes, looked up before the built-in instructions
+    pub opcode_table: HashMap<String, String>,
+    /// Check that the generated code is well-formed Hack assembly before returning it
+    pub validate_output: bool,
+    /// Stop at the first instruction that can't be translated instead of reporting all of them
+    pub fail_fast: bool,
+    /// Separate blocks with a single newline instead of a blank line, leaving out all blank lines
+    pub compact: bool,
+    /// Explain the steps of the call and return sequences with comments
+    pub annotate_frames: bool,
+    /// Replace the references to generated labels with the ROM addresses they stand for
+    pub resolve_labels: bool,
+    /// The function the bootstrap starts the program with
+    pub entry: String,
+    /// Constants the bootstrap calls the entry function with, jumping to it if there are none
+    pub entry_args: Vec<u16>,
+    /// Prefix of every label the output defines, including the functions' and the
+    /// bootstrap's and halt loop's, so that outputs translated with distinct prefixes
+    /// can be concatenated
+    pub label_prefix: String,
+    /// Emit exactly one instruction or label per line, without comments, indentation
+    /// or blank lines, for tools parsing assembly strictly
+    pub strict_asm: bool,
+    /// Mark the start of each basic block with a `// BB <frame> <index>` comment
+    pub emit_blocks: bool,
+    /// Most instructions the output may have, 32768 filling the Hack ROM
+    pub max_instructions: usize,
+    /// Functions defined by files translated separately, known to exist
+    /// though the translated files don't define them
+    pub external_functions: Vec<String>,
+    /// Rename the labels to short names, `L0`, `L1`, ...
+    pub minify_labels: bool,
+    /// Move the comments describing each instruction to the end of its first line,
+    /// aligned into a second column
+    pub align_comments: bool,
+    /// The 0-based column aligned comments start at
+    pub comment_column: usize,
+    /// Name the templates each block was filled from with a `// template: <path>` comment
+    pub trace_templates: bool,
+    /// Assembly inserted right after the bootstrap, like runtime stubs or trap handlers
+    pub asm_header: String,
+    /// Assembly appended at the end of the output, after the halt loop
+    pub asm_footer: String,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            qualify_labels: false,
+            strip_unused_functions: false,
+            include_line_directives: false,
+            trace: false,
+            dialect: Dialect::default(),
+            unknown_opcode: UnknownOpcode::default(),
+            missing_entry: MissingEntry::default(),
+            assert_address: 16383,
+            static_threshold: 240,
+            local_slack: 2,
+            pop_scratch: 13,
+            pointer_base: 3,
+            banner: false,
+            emit_halt: false,
+            sections: false,
+            optimize: false,
+            profile: false,
+            stable_labels: false,
+            opcode_table: HashMap::new(),
+            validate_output: false,
+            fail_fast: false,
+            compact: false,
+            annotate_frames: false,
+            resolve_labels: false,
+            entry: "Sys.init".to_string(),
+            entry_args: vec![],
+            label_prefix: String::new(),
+            strict_asm: false,
+            emit_blocks: false,
+            max_instructions: 32768,
+            external_functions: vec![],
+            minify_labels: false,
+            align_comments: false,
+            comment_column: 32,
+            trace_templates: false,
+            asm_header: String::new(),
+            asm_footer: String::new(),
+        }
+    }
+}
+
+/// Implements a chainable setter for each of the given fields of [`Options`],
+/// so that options can be built as `Options::default().optimize(true).entry("Main.main")`
+macro_rules! setters {
+    ($($name:ident: $ty:ty,)*) => {
+        impl Options {
+            $(
+                #[doc = concat!("Sets [`Options::", stringify!($name), "`]")]
+                pub fn $name(mut self, value: impl Into<$ty>) -> Self {
+                    self.$name = value.into();
+                    self
+                }
+            )*
+        }
+    };
+}
+
+setters! {
+    qualify_labels: bool,
+    strip_unused_functions: bool,
+    include_line_directives: bool,
+    trace: bool,
+    dialect: Dialect,
+    unknown_opcode: UnknownOpcode,
+    missing_entry: MissingEntry,
+    assert_address: u16,
+    static_threshold: u16,
+    local_slack: usize,
+    pop_scratch: u16,
+    pointer_base: u16,
+    banner: bool,
+    emit_halt: bool,
+    sections: bool,
+    optimize: bool,
+    profile: bool,
+    stable_labels: bool,
+    opcode_table: HashMap<String, String>,
+    validate_output: bool,
+    fail_fast: bool,
+    compact: bool,
+    annotate_frames: bool,
+    resolve_labels: bool,
+    entry: String,
+    entry_args: Vec<u16>,
+    label_prefix: String,
+    strict_asm: bool,
+    emit_blocks: bool,
+    max_instructions: usize,
+    external_functions: Vec<String>,
+    minify_labels: bool,
+    align_comments: bool,
+    comment_column: usize,
+    trace_templates: bool,
+    asm_header: String,
+    asm_footer: String,
+}
+
+impl Options {
+    /// Returns the command line flags that select the options affecting the output
+    fn flags(&self) -> Vec<String> {
+        let default = Options::default();
+        let mut flags = vec![];
+        if self.qualify_labels {
+            flags.push("--qualify-labels".to_string());
+        }
+        if self.strip_unused_functions {
+            flags.push("--strip-unused-functions".to_string());
+        }
+        if self.include_line_directives {
+            flags.push("--include-line-directives".to_string());
+        }
+        if self.trace {
+            flags.push("--trace".to_string());
+        }
+        if self.emit_halt {
+            flags.push("--emit-halt".to_string());
+        }
+        if self.sections {
+            flags.push("--sections".to_string());
+        }
+        if self.optimize {
+            flags.push("--optimize".to_string());
+        }
+        if self.profile {
+            flags.push("--profile".to_string());
+        }
+        if self.stable_labels {
+            flags.push("--stable-labels".to_string());
+        }
+        if !self.opcode_table.is_empty() {
+            let mut opcodes = self.opcode_table.keys().cloned().collect::<Vec<String>>();
+            opcodes.sort();
+            flags.push(format!("--opcode-table ({})", opcodes.join(", ")));
+        }
+        if self.compact {
+            flags.push("--compact".to_string());
+        }
+        if self.annotate_frames {
+            flags.push("--annotate-frames".to_string());
+        }
+        if self.resolve_labels {
+            flags.push("--resolve-labels".to_string());
+        }
+        if self.entry != default.entry {
+            flags.push(format!("--entry {}", self.entry));
+        }
+        for arg in &self.entry_args {
+            flags.push(format!("--arg {}", arg));
+        }
+        if !self.label_prefix.is_empty() {
+            flags.push(format!("--label-prefix {}", self.label_prefix));
+        }
+        if self.strict_asm {
+            flags.push("--strict-asm".to_string());
+        }
+        if self.emit_blocks {
+            flags.push("--emit-blocks".to_string());
+        }
+        if self.minify_labels {
+            flags.push("--minify-labels".to_string());
+        }
+        if self.trace_templates {
+            flags.push("--trace-templates".to_string());
+        }
+        if !self.asm_header.is_empty() {
+            flags.push("--asm-header".to_string());
+        }
+        if !self.asm_footer.is_empty() {
+            flags.push("--asm-footer".to_string());
+        }
+        if self.align_comments {
+            flags.push("--align-comments".to_string());
+            if self.comment_column != default.comment_column {
+                flags.push(format!("--comment-column {}", self.comment_column));
+            }
+        }
+        match self.unknown_opcode {
+            UnknownOpcode::Error => (),
+            UnknownOpcode::Skip => flags.push("--unknown-opcode skip".to_string()),
+            UnknownOpcode::Comment => flags.push("--unknown-opcode comment".to_string()),
+        }
+        if self.missing_entry == MissingEntry::Halt {
+            flags.push("--missing-entry halt".to_string());
+        }
+        if self.dialect == Dialect::Extended {
+            flags.push("--dialect extended".to_string());
+        }
+        if self.assert_address != default.assert_address {
+            flags.push(format!("--assert-address {}", self.assert_address));
+        }
+        if self.pop_scratch != default.pop_scratch {
+            flags.push(format!("--pop-scratch R{}", self.pop_scratch));
+        }
+        if self.pointer_base != default.pointer_base {
+            flags.push(format!("--pointer-base {}", self.pointer_base));
+        }
+        flags
+    }
+}
+
+/// Returns the stem of the file defining the function with the given name,
+/// falling back to the class part of a `Class.function` name if none does
+fn defining_file<'a>(name: &'a str, instructions: &[Instruction<'a>]) -> Option<&'a str> {
+    defined_functions(instructions)
+        .into_iter()
+        .find(|x| x.name == name)
+        .map(|x| x.file)
+        .or_else(|| name.split_once('.').map(|(class, _)| class))
+}
+
+/// Returns the label marking the entry point of a function, after the label prefix,
+/// qualified with the stem of its defining file if requested
+fn function_label(name: &str, file: Option<&str>, options: &Options) -> String {
+    match file {
+        Some(f) if options.qualify_labels => format!("{}{}.{}", options.label_prefix, f, name),
+        _ => options.label_prefix.clone() + name,
+    }
+}
+
+/// Returns the file-qualified name of the frame an instruction is part of,
+/// which the labels generated for the instruction start with
+fn frame_label(instruction: &Instruction, options: &Options) -> String {
+    format!(
+        "{}{}.{}",
+        options.label_prefix,
+        instruction.file,
+        instruction.frame.unwrap_or("global")
+    )
+}
+
+/// Hands out the return address labels of function calls,
+/// numbering them with a counter so that no two calls share a label
+#[derive(Default)]
+pub struct ReturnLabelAllocator {
+    next: usize,
+}
+
+impl ReturnLabelAllocator {
+    /// Returns a new unique return address label for a call made from the given frame
+    pub fn allocate(&mut self, frame: &str) -> String {
+        self.next += 1;
+        format!("{}$ret.{}", frame, self.next - 1)
+    }
+}
+
+/// Returns the net change in stack height caused by the VM instruction,
+/// or None if it can't be determined (return, invalid instructions)
+fn stack_delta(instruction: &Instruction) -> Option<i32> {
+    match instruction.command().ok()? {
+        Command::Push { .. } => Some(1),
+        Command::Pop { .. } | Command::IfGoto(_) | Command::Assert => Some(-1),
+        Command::Add | Command::Sub | Command::And | Command::Or => Some(-1),
+        Command::Eq | Command::Gt | Command::Lt => Some(-1),
+        Command::Neg | Command::Not | Command::Label(_) | Command::Goto(_) => Some(0),
+        Command::Function { n_vars, .. } => Some(n_vars as i32),
+        Command::Call { n_args, .. } => Some(1 - n_args as i32),
+        Command::Return => None,
+    }
+}
+
+/// Checks that the generated code neither defines nor jumps to
+/// a label that is one of the backend's reserved symbols
+fn check_reserved_labels(backend: &dyn Backend, code: &str) -> Result<(), String> {
+    let lines = code.lines().collect::<Vec<&str>>();
+    let defined = lines
+        .iter()
+        .filter_map(|x| x.strip_prefix('(')?.strip_suffix(')'));
+    let jumped_to = lines
+        .windows(2)
+        .filter(|x| x[1].contains(";J"))
+        .filter_map(|x| x[0].strip_prefix('@'));
+    match defined
+        .chain(jumped_to)
+        .find(|x| backend.reserved_symbols().contains(x))
+    {
+        Some(x) => Err(format!("label '{}' shadows a predefined symbol", x)),
+        None => Ok(()),
+    }
+}
+
+/// Returns the number of arguments a VM operation takes,
+/// or None if the operation is unknown
+fn arity(operation: &str) -> Option<usize> {
+    match operation {
+        "push" | "pop" | "function" | "call" => Some(2),
+        "label" | "goto" | "if-goto" => Some(1),
+        "add" | "sub" | "and" | "or" | "neg" | "not" | "eq" | "gt" | "lt" => Some(0),
+        "return" | "assert" => Some(0),
+        _ => None,
+    }
+}
+
+/// Returns the Hack assembly representation of the VM instruction
+fn generate_code(
+    backend: &dyn Backend,
+    instruction: &Instruction,
+    options: &Options,
+    return_labels: &mut ReturnLabelAllocator,
+) -> Result<String, String> {
+    let err_at = |column: usize, x: String| {
+        format!(
+            "{}.vm:{}:{} #{} '{}': {}",
+            instruction.file, instruction.line, column, instruction.id, instruction.raw, x
+        )
+    };
+    let err_fmt = |x: String| err_at(instruction.error_column(&x), x);
+    let custom = options.opcode_table.get(instruction.operation);
+    if let Some(n) = arity(instruction.operation)
+        .filter(|_| custom.is_none())
+        .filter(|n| instruction.arg_count() > *n)
+    {
+        let mut surplus = instruction.token_columns().nth(n + 1).unwrap().0;
+        let mut message = format!(
+            "'{}' takes {} argument{}, got {}",
+            instruction.operation,
+            n,
+            if n == 1 { "" } else { "s" },
+            instruction.arg_count()
+        );
+        // a count after the stray tokens is likely a name split by whitespace
+        let split_name = matches!(instruction.operation, "function" | "call")
+            && instruction
+                .arg2
+                .is_some_and(|x| x.parse::<usize>().is_err());
+        if split_name {
+            surplus = instruction.token_columns().nth(2).unwrap().0;
+            message += ", function names can't contain whitespace";
+        }
+        Err(err_at(surplus, message))?;
+    }
+    let mut header = String::new();
+    if options.include_line_directives {
+        header += &format!("// #line {} {}.vm\n", instruction.line, instruction.file);
+    }
+    header += &format!("// {}\n", instruction.raw);
+    if let (true, None, Some(delta)) = (options.trace, custom, stack_delta(instruction)) {
+        header += &format!("// stack: {:+}\n", delta);
+    }
+    let g = |code: Result<String, String>| {
+        let code = code.map_err(err_fmt)?;
+        check_reserved_labels(backend, &code).map_err(err_fmt)?;
+        Ok(header + code.trim_end())
+    };
+    if let Some(template) = custom {
+        return g(opcodes::expand_template(template, instruction));
+    }
+    match instruction.operation {
+        "push" | "pop" => g(backend.generate_memop(instruction, options)),
+        "add" | "sub" | "and" | "or" => g(backend.generate_2op(instruction, options)),
+        "neg" | "not" => g(backend.generate_1op(instruction, options)),
+        "eq" | "gt" | "lt" => g(backend.generate_cmp(instruction, options)),
+        "label" | "goto" | "if-goto" => g(backend.generate_branching(instruction, options)),
+        "function" | "call" | "return" => {
+            g(backend.generate_functions(instruction, options, return_labels))
+        }
+        "assert" if options.dialect == Dialect::Extended => {
+            g(backend.generate_assert(instruction, options))
+        }
+        o => match options.unknown_opcode {
+            UnknownOpcode::Error => Err(err_fmt(format!("Invalid VM instruction '{}'", o))),
+            UnknownOpcode::Skip => Ok(String::new()),
+            UnknownOpcode::Comment => Ok(format!("// unknown: {}", instruction.raw)),
+        },
+    }
+}
+
+/// Returns the names of the functions reachable through calls from the entry function,
+/// or None if the entry function isn't defined
+fn reachable_functions<'a>(
+    entry: &'a str,
+    instructions: &[Instruction<'a>],
+) -> Option<HashSet<&'a str>> {
+    defined_functions(instructions)
+        .iter()
+        .find(|x| x.name == entry)?;
+    let mut call_graph: HashMap<&str, Vec<&str>> = HashMap::new();
+    for x in instructions.iter().filter(|x| x.operation == "call") {
+        if let (Some(caller), Some(callee)) = (x.frame, x.arg1) {
+            call_graph.entry(caller).or_default().push(callee);
+        }
+    }
+    let mut reachable = HashSet::from([entry]);
+    let mut pending = vec![entry];
+    while let Some(f) = pending.pop() {
+        for &callee in call_graph.get(f).into_iter().flatten() {
+            if reachable.insert(callee) {
+                pending.push(callee);
+            }
+        }
+    }
+    Some(reachable)
+}
+
+/// Returns a description of the given VM opcode together with the Hack assembly
+/// generated for a representative instruction using it, for learning purposes
+pub fn explain(opcode: &str) -> Result<String, String> {
+    let (sample, description) = match opcode {
+        "push" => (
+            "push local 2",
+            "pushes the value of segment[index] onto the stack",
+        ),
+        "pop" => (
+            "pop local 2",
+            "pops the top of the stack into segment[index]",
+        ),
+        "add" => ("add", "pops y and x, then pushes x + y"),
+        "sub" => ("sub", "pops y and x, then pushes x - y"),
+        "and" => ("and", "pops y and x, then pushes x & y"),
+        "or" => ("or", "pops y and x, then pushes x | y"),
+        "neg" => ("neg", "replaces the top of the stack with its negation"),
+        "not" => ("not", "replaces the top of the stack with its bitwise not"),
+        "eq" => (
+            "eq",
+            "pops y and x, then pushes true (-1) if x = y, else false (0)",
+        ),
+        "gt" => (
+            "gt",
+            "pops y and x, then pushes true (-1) if x > y, else false (0)",
+        ),
+        "lt" => (
+            "lt",
+            "pops y and x, then pushes true (-1) if x < y, else false (0)",
+        ),
+        "label" => (
+            "label LOOP",
+            "marks a jump destination within the current function",
+        ),
+        "goto" => ("goto LOOP", "jumps unconditionally to a label"),
+        "if-goto" => (
+            "if-goto LOOP",
+            "pops the top of the stack and jumps if it isn't 0",
+        ),
+        "function" => (
+            "function Main.main 2",
+            "declares a function with n local variables",
+        ),
+        "call" => (
+            "call Math.multiply 2",
+            "calls a function with n arguments on the stack",
+        ),
+        "return" => ("return", "returns the top of the stack to the caller"),
+        o => Err(format!("Invalid VM instruction '{}'", o))?,
+    };
+    let mut instruction = Instruction::new(sample, 0, 1, "Main")?;
+    instruction.frame = Some("Main.main");
+    let code = generate_code(
+        &Hack,
+        &instruction,
+        &Options::default(),
+        &mut ReturnLabelAllocator::default(),
+    )?;
+    Ok(format!("// {}: {}\n{}\n", opcode, description, code))
+}
+
+/// Given a slice of tuples of a VM filename and its contents, returns the
+/// instructions of all files with their frames and called files resolved
+fn parse_program(contents: &[(String, String)]) -> Vec<Instruction<'_>> {
+    let instructions = contents
+        .iter()
+        .flat_map(|(file, c)| parse(file, c).map(|x| x.unwrap()))
+        .collect::<Vec<Instruction>>();
+    let instructions_clone = instructions.clone();
+    instructions
+        .into_iter()
+        .map(|mut x| {
+            x.set_frame(&instructions_clone);
+            x.set_callee_file(&instructions_clone);
+            x
+        })
+        .collect()
+}
+
+/// Returns the code ending with a newline unless it's empty,
+/// so that the code following it starts on a line of its own
+fn with_newline(code: &str) -> String {
+    match code.is_empty() || code.ends_with('\n') {
+        true => code.to_string(),
+        false => code.to_string() + "\n",
+    }
+}
+
+/// Returns the assembly with each `// cost:`, `// bootstrap cost:` and `// halt cost:`
+/// comment giving the number of instructions since the previous one, so that,
+/// counted after optimizing, they add up to the total
+fn count_costs(code: &str) -> String {
+    let mut output = String::new();
+    let mut count = 0;
+    for line in code.lines() {
+        let marker = ["// cost:", "// bootstrap cost:", "// halt cost:"]
+            .into_iter()
+            .find(|x| line.trim().starts_with(x));
+        match marker {
+            Some(marker) => {
+                output += &format!("{} {}\n", marker, count);
+                count = 0;
+            }
+            None => {
+                count += count_instructions(line);
+                output += line;
+                output += "\n";
+            }
+        }
+    }
+    output
+}
+
+/// Returns the number of real A- and C-instructions in the assembly,
+/// leaving out comments, labels and empty lines
+pub fn count_instructions(code: &str) -> usize {
+    code.lines()
+        .map(|x| x.trim())
+        .filter(|x| !(x.is_empty() || x.starts_with("//") || x.starts_with('(')))
+        .count()
+}
+
+/// Returns the assembly with the comment lines directly above an instruction or label,
+/// other than the section markers, moved to the end of that line and joined by `; `.
+/// Every comment following code starts at the given column, or one space past the code
+/// if the code reaches the column
+fn align_comments(code: &str, column: usize) -> String {
+    let mut output = String::new();
+    let mut comments: Vec<&str> = vec![];
+    for line in code.lines() {
+        let (text, comment) = match line.split_once("//") {
+            Some((text, comment)) => (text.trim_end(), Some(comment.trim())),
+            None => (line.trim_end(), None),
+        };
+        if text.trim().is_empty() {
+            match comment {
+                // the section markers head what follows rather than describe it
+                Some(comment) if comment.starts_with("=====") || comment.starts_with("-----") => {
+                    output += &format!("// {}\n", comment);
+                }
+                Some(comment) => comments.push(comment),
+                None => {
+                    // the comments describe no line of code
+                    for x in comments.drain(..) {
+                        output += &format!("// {}\n", x);
+                    }
+                    output += "\n";
+                }
+            }
+            continue;
+        }
+        comments.extend(comment);
+        match comments.is_empty() {
+            true => output += text,
+            false => {
+                let width = column.max(text.len() + 1);
+                output += &format!("{:<width$}// {}", text, comments.join("; "));
+            }
+        }
+        output += "\n";
+        comments.clear();
+    }
+    for x in comments {
+        output += &format!("// {}\n", x);
+    }
+    output
+}
+
+/// Returns the comments marking the start of a new file or function at
+/// the given instruction, given the file of the previous instruction
+fn section_markers(instruction: &Instruction, previous_file: Option<&str>) -> String {
+    let mut markers = String::new();
+    if previous_file != Some(instruction.file) {
+        markers += &format!("// ===== File: {}.vm =====\n", instruction.file);
+    }
+    if instruction.operation == "function" {
+        markers += &format!(
+            "// ----- function {} -----\n",
+            instruction.arg1.unwrap_or_default()
+        );
+    }
+    markers
+}
+
+/// Returns the comment block describing the translation unit
+fn banner(
+    contents: &[(String, String)],
+    instructions: &[Instruction],
+    options: &Options,
+) -> String {
+    let files = contents
+        .iter()
+        .map(|(file, _)| file.to_string() + ".vm")
+        .collect::<Vec<String>>();
+    let flags = options.flags();
+    format!(
+        "// Generated by vmtranslator\n// Input files: {}\n// Instructions: {}\n// Options: {}\n\n",
+        files.join(", "),
+        instructions.len(),
+        match flags.is_empty() {
+            true => "none".to_string(),
+            false => flags.join(" "),
+        }
+    )
+}
+
+/// Given a vector of tuples of a VM filename and its contents,
+/// return the translated Hack assembly code.
+/// The files are translated and concatenated in the order of the vector
+pub fn translate(
+    contents: Vec<(String, String)>,
+    options: &Options,
+) -> Result<String, Vec<String>> {
+    translate_with(&Hack, contents, options)
+}
+
+/// A VM file given to [`translate_ordered`]
+pub struct FileInput {
+    /// The file stem, naming the file's statics
+    pub name: String,
+    pub source: String,
+    /// Emit this file's code before the other files, right after the bootstrap
+    pub bootstrap: bool,
+}
+
+/// Translates the files like [`translate`], except that the file marked as the
+/// bootstrap file, usually the one defining `Sys.init`, always comes first.
+/// The other files keep their order
+pub fn translate_ordered(files: Vec<FileInput>, options: &Options) -> Result<String, Vec<String>> {
+    let (first, rest): (Vec<FileInput>, Vec<FileInput>) =
+        files.into_iter().partition(|x| x.bootstrap);
+    if first.len() > 1 {
+        Err(first
+            .iter()
+            .map(|x| {
+                format!(
+                    "{}.vm: more than one file is marked as the bootstrap file",
+                    x.name
+                )
+            })
+            .collect::<Vec<String>>())?;
+    }
+    let contents = first
+        .into_iter()
+        .chain(rest)
+        .map(|x| (x.name, x.source))
+        .collect();
+    translate(contents, options)
+}
+
+/// Given a vector of tuples of a VM filename and its contents,
+/// return the code translated by the given backend
+pub fn translate_with(
+    backend: &dyn Backend,
+    contents: Vec<(String, String)>,
+    options: &Options,
+) -> Result<String, Vec<String>> {
+    translate_blocks(backend, contents, options, None, None)
+}
+
+/// A pass rewriting the blocks of assembly of the instructions, given to [`translate_with_hook`]
+pub type BlockHook = dyn Fn(&mut Vec<String>);
+
+/// Like [`translate_with`], but passes the block of assembly of each instruction
+/// to the hook before they're joined, letting the caller rewrite them with passes
+/// of its own. The hook may change the blocks but not their number
+pub fn translate_with_hook(
+    backend: &dyn Backend,
+    contents: Vec<(String, String)>,
+    options: &Options,
+    hook: &BlockHook,
+) -> Result<String, Vec<String>> {
+    translate_blocks(backend, contents, options, None, Some(hook))
+}
+
+/// Translates the program like [`translate_with`], returning each file's name paired
+/// with just its own blocks, laid out and labelled as in the translation of the whole
+/// program. The bootstrap, the halt loop and the passes over the whole output,
+/// like optimizing or resolving labels, are left out
+pub fn translate_per_file(
+    backend: &dyn Backend,
+    contents: Vec<(String, String)>,
+    options: &Options,
+) -> Result<Vec<(String, String)>, Vec<String>> {
+    check_duplicate_files(&contents)?;
+    let mut instructions = parse_program(&contents);
+    if options.stable_labels {
+        incremental::assign_stable_ids(&mut instructions);
+    }
+    let (blocks, errors) = generate_blocks(backend, &instructions, options, None);
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+    let mut files = contents
+        .iter()
+        .map(|(file, _)| (file.clone(), String::new()))
+        .collect::<Vec<(String, String)>>();
+    for (x, code) in blocks {
+        let (_, output) = files.iter_mut().find(|(file, _)| file == x.file).unwrap();
+        *output += &code;
+        *output += match options.compact {
+            true => "\n",
+            false => "\n\n",
+        };
+    }
+    if options.compact {
+        for (_, output) in &mut files {
+            *output = output
+                .lines()
+                .filter(|x| !x.is_empty())
+                .map(|x| x.to_string() + "\n")
+                .collect();
+        }
+    }
+    Ok(files)
+}
+
+/// Translates each instruction of the program not stripped as unused into its block
+/// of assembly, taking the blocks of unchanged instructions from the cache if one is given.
+/// Returns the instructions paired with their blocks and the errors
+fn generate_blocks<'a, 'b>(
+    backend: &dyn Backend,
+    instructions: &'b [Instruction<'a>],
+    options: &Options,
+    mut cache: Option<&mut TranslationCache>,
+) -> (Vec<(&'b Instruction<'a>, String)>, Vec<String>) {
+    let reachable = match options.strip_unused_functions {
+        true => reachable_functions(&options.entry, instructions),
+        false => None,
+    };
+    let mut return_labels = ReturnLabelAllocator::default();
+    let (mut blocks, mut errors) = (vec![], vec![]);
+    for x in instructions.iter().filter(|x| match (&reachable, x.frame) {
+        (Some(r), Some(f)) => r.contains(f),
+        _ => true,
+    }) {
+        let mut generate = || generate_code(backend, x, options, &mut return_labels);
+        let code = match cache.as_deref_mut() {
+            Some(cache) => cache.block(x, options, generate),
+            None => generate(),
+        };
+        match code {
+            // skipped instructions
+            Ok(code) if code.is_empty() => (),
+            Ok(code) => blocks.push((x, code)),
+            Err(e) => {
+                errors.push(e);
+                if options.fail_fast {
+                    break;
+                }
+            }
+        }
+    }
+    (blocks, errors)
+}
+
+/// Checks that no two input files have the same name,
+/// as their static variables would silently share memory,
+/// telling apart the same file given twice
+fn check_duplicate_files(contents: &[(String, String)]) -> Result<(), Vec<String>> {
+    let mut seen = HashMap::new();
+    let errors = contents
+        .iter()
+        .filter_map(|(file, source)| match seen.insert(file, source) {
+            Some(other) if other == source => Some(format!(
+                "{}.vm: the same file is given more than once, its functions would be defined twice",
+                file
+            )),
+            Some(_) => Some(format!(
+                "{}.vm: more than one input file has this name, their '{}.N' statics would share memory",
+                file, file
+            )),
+            None => None,
+        })
+        .collect::<Vec<String>>();
+    match errors.is_empty() {
+        true => Ok(()),
+        false => Err(errors),
+    }
+}
+
+/// Checks that no two blocks define the same label, as the labels of distinct
+/// functions or branches can collide once qualified with their file and frame
+fn check_duplicate_labels(blocks: &[(&Instruction, String)]) -> Result<(), Vec<String>> {
+    let mut seen: HashMap<&str, &Instruction> = HashMap::new();
+    let mut errors = vec![];
+    for (x, code) in blocks {
+        let labels = code
+            .lines()
+            .filter_map(|line| line.trim().strip_prefix('(')?.strip_suffix(')'));
+        for label in labels {
+            if let Some(first) = seen.insert(label, x) {
+                errors.push(format!(
+                    "{}.vm:{}:{} #{} '{}': label '{}' is already defined by {}.vm:{}:{} #{} '{}'",
+                    x.file,
+                    x.line,
+                    x.column,
+                    x.id,
+                    x.raw,
+                    label,
+                    first.file,
+                    first.line,
+                    first.column,
+                    first.id,
+                    first.raw
+                ));
+                // the first definition stays the one later duplicates are reported against
+                seen.insert(label, first);
+            }
+        }
+    }
+    match errors.is_empty() {
+        true => Ok(()),
+        false => Err(errors),
+    }
+}
+
+/// Translates the program with the given backend,
+/// taking the blocks of unchanged instructions from the cache if one is given
+/// and passing the blocks through the hook if one is given
+fn translate_blocks(
+    backend: &dyn Backend,
+    contents: Vec<(String, String)>,
+    options: &Options,
+    cache: Option<&mut TranslationCache>,
+    hook: Option<&BlockHook>,
+) -> Result<String, Vec<String>> {
+    check_duplicate_files(&contents)?;
+    let mut instructions = parse_program(&contents);
+    if options.stable_labels {
+        incremental::assign_stable_ids(&mut instructions);
+    }
+    for (file, _) in &contents {
+        let count = instructions.iter().filter(|x| x.file == file).count();
+        log!(Info, "{}.vm: {} instructions", file, count);
+    }
+    let banner = match options.banner {
+        true => banner(&contents, &instructions, options),
+        false => String::new(),
+    };
+    let mut init = backend.bootstrap(
+        &function_label(
+            &options.entry,
+            defining_file(&options.entry, &instructions),
+            options,
+        ),
+        &options.entry_args,
+        options,
+    );
+    let missing_entry = !options.external_functions.contains(&options.entry)
+        && !defined_functions(&instructions)
+            .iter()
+            .any(|x| x.name == options.entry);
+    let trapped = missing_entry && options.missing_entry == MissingEntry::Halt;
+    if trapped {
+        init = backend.halting_bootstrap(options);
+    }
+    // a single file may be a test script run without the bootstrap's call
+    if contents.len() > 1 && !init.is_empty() && missing_entry && !trapped {
+        Err(vec![format!(
+            "bootstrap requires a {} function, none found",
+            options.entry
+        )])?;
+    }
+    let mut res = generate_blocks(backend, &instructions, options, cache);
+    if let (Some(hook), true) = (hook, res.1.is_empty()) {
+        let (blocks, mut codes): (Vec<&Instruction>, Vec<String>) = res.0.into_iter().unzip();
+        hook(&mut codes);
+        if codes.len() != blocks.len() {
+            Err(vec![format!(
+                "the hook changed the number of blocks from {} to {}",
+                blocks.len(),
+                codes.len()
+            )])?;
+        }
+        res.0 = blocks.into_iter().zip(codes).collect();
+    }
+    if res.1.is_empty() {
+        check_duplicate_labels(&res.0)?;
+    }
+    // the halting bootstrap already ends in the halt loop
+    let halt = match options.emit_halt && !trapped {
+        true => backend.halt(options),
+        false => String::new(),
+    };
+    match res.1.len() {
+        0 => {
+            let mut output = banner;
+            if options.sections {
+                output += "// ===== Bootstrap =====\n";
+            }
+            output += &init;
+            output += &with_newline(&options.asm_header);
+            if options.profile {
+                output += "// bootstrap cost: 0\n";
+            }
+            let block_starts = match options.emit_blocks {
+                true => {
+                    analysis::basic_block_starts(&res.0.iter().map(|x| x.0).collect::<Vec<_>>())
+                }
+                false => vec![None; res.0.len()],
+            };
+            for (i, (x, code)) in res.0.iter().enumerate() {
+                if options.sections {
+                    let previous = i.checked_sub(1).map(|j| res.0[j].0.file);
+                    output += &section_markers(x, previous);
+                }
+                if let Some((frame, index)) = block_starts[i] {
+                    output += &format!("// BB {} {}\n", frame, index);
+                }
+                output += code;
+                if options.profile {
+                    // counted once the passes over the whole output are done
+                    output += "\n// cost: 0";
+                }
+                output += match options.compact {
+                    true => "\n",
+                    false => "\n\n",
+                };
+            }
+            output += &halt;
+            output += &with_newline(&options.asm_footer);
+            if options.profile && !(halt.is_empty() && options.asm_footer.is_empty()) {
+                output += "// halt cost: 0\n";
+            }
+            if options.compact {
+                // the banner and templates have blank lines of their own
+                output = output
+                    .lines()
+                    .filter(|x| !x.is_empty())
+                    .map(|x| x.to_string() + "\n")
+                    .collect();
+            }
+            if options.optimize {
+                output = optimize::peephole(&output);
+            }
+            if options.minify_labels {
+                output = link::minify_labels(&output);
+            }
+            if options.resolve_labels {
+                output = link::resolve_labels(&output);
+            }
+            if options.profile {
+                output = count_costs(&output);
+            }
+            let count = count_instructions(&output);
+            if count > options.max_instructions {
+                Err(vec![format!(
+                    "the output has {} instructions, more than the limit of {}",
+                    count, options.max_instructions
+                )])?;
+            }
+            if options.validate_output {
+                validate_assembly(&output)?;
+            }
+            if options.profile {
+                output += &format!("// total cost: {}\n", count_instructions(&output));
+            }
+            if options.align_comments {
+                output = align_comments(&output, options.comment_column);
+            }
+            if options.strict_asm {
+                output = output
+                    .lines()
+                    .map(|x| x.split("//").next().unwrap().trim())
+                    .filter(|x| !x.is_empty())
+                    .map(|x| x.to_string() + "\n")
+                    .collect();
+            }
+            log!(
+                Info,
+                "translated {} instructions into {} lines",
+                instructions.len(),
+                output.lines().count()
+            );
+            Ok(output)
+        }
+        _ => {
+            log!(Error, "translation failed with {} errors", res.1.len());
+            Err(res.1)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Returns the instructions and labels of the assembly, without comments or blank lines
+    fn code_lines(code: &str) -> Vec<&str> {
+        code.lines()
+            .map(|x| x.split("//").next().unwrap().trim())
+            .filter(|x| !x.is_empty())
+            .collect()
+    }
+
+    /// Returns the program of the fixture files, by their names without `.vm`
+    fn fixture(files: &[(&str, &str)]) -> Vec<(String, String)> {
+        files
+            .iter()
+            .map(|(name, source)| (name.to_string(), source.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn fixtures_are_unchanged() {
+        let single = fixture(&[("function_test", include_str!("../tests/function_test.vm"))]);
+        assert_eq!(
+            translate(single, &Options::default()).unwrap(),
+            include_str!("../tests/function_test.asm")
+        );
+        let cross_file = fixture(&[
+            ("Main", include_str!("../tests/cross_file/Main.vm")),
+            ("Sys", include_str!("../tests/cross_file/Sys.vm")),
+        ]);
+        assert_eq!(
+            translate(cross_file, &Options::default().qualify_labels(true)).unwrap(),
+            include_str!("../tests/cross_file/cross_file.asm")
+        );
+    }
+
+    /// Returns the numbers of the comments starting with the prefix
+    fn costs(output: &str, prefix: &str) -> Vec<usize> {
+        output
+            .lines()
+            .filter_map(|x| x.strip_prefix(prefix))
+            .map(|x| x.trim().parse().unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn profile_costs_add_up_to_the_total() {
+        let source = "function Sys.init 0\npush constant 1\npush constant 2\nlt\nif-goto end\n\
+                      push constant 3\npop temp 0\nlabel end\ngoto end\n";
+        for options in [
+            Options::default().profile(true),
+            Options::default().profile(true).optimize(true),
+            Options::default()
+                .profile(true)
+                .optimize(true)
+                .emit_halt(true),
+        ] {
+            let output = translate(fixture(&[("Sys", source)]), &options).unwrap();
+            let parts = costs(&output, "// bootstrap cost:")
+                .into_iter()
+                .chain(costs(&output, "// cost:"))
+                .chain(costs(&output, "// halt cost:"));
+            assert_eq!(costs(&output, "// total cost:"), [parts.sum::<usize>()]);
+            assert_eq!(costs(&output, "// bootstrap cost:"), [12]);
+        }
+    }
+
+    #[test]
+    fn align_comments_moves_comments_to_the_column() {
+        let code = "// ===== Bootstrap =====\n@256\nD=A\n\n// push constant 1\n// stack: +1\n@1\nD=A // load\n// dangling\n";
+        let aligned = align_comments(code, 12);
+        assert_eq!(
+            aligned,
+            "// ===== Bootstrap =====\n@256\nD=A\n\n@1          // push constant 1; stack: +1\nD=A         // load\n// dangling\n"
+        );
+        assert_eq!(code_lines(&aligned), code_lines(code));
+        for line in aligned.lines().filter(|x| !x.starts_with("//")) {
+            if let Some(column) = line.find("//") {
+                assert_eq!(column, 12, "{}", line);
+            }
+        }
+    }
+
+    #[test]
+    fn align_comments_past_long_code() {
+        assert_eq!(
+            align_comments("// x\n@Main.main$ret.0\n", 4),
+            "@Main.main$ret.0 // x\n"
+        );
+    }
+
+    /// Returns the labels the assembly defines
+    fn defined_labels(code: &str) -> Vec<&str> {
+        code_lines(code)
+            .into_iter()
+            .filter_map(|x| x.strip_prefix('(')?.strip_suffix(')'))
+            .collect()
+    }
+
+    #[test]
+    fn prefixed_outputs_share_no_labels() {
+        let source = "function Sys.init 0\npush constant 1\npush constant 2\nlt\n\
+                      call Sys.f 0\nlabel loop\ngoto loop\nfunction Sys.f 0\npush constant 0\nreturn\n";
+        let output = |prefix: &str, options: Options| {
+            translate(fixture(&[("Sys", source)]), &options.label_prefix(prefix)).unwrap()
+        };
+        for options in [
+            Options::default().emit_halt(true),
+            Options::default().qualify_labels(true).entry_args(vec![1]),
+            Options::default().entry("Sys.main"),
+        ] {
+            let options = options.missing_entry(MissingEntry::Halt);
+            let (first, second) = (output("P1_", options.clone()), output("P2_", options));
+            let labels = defined_labels(&first);
+            assert!(labels.iter().all(|x| x.starts_with("P1_")), "{:?}", labels);
+            assert!(defined_labels(&second).iter().all(|x| !labels.contains(x)));
+        }
+        let emulator = crate::run_vm(
+            fixture(&[("Sys", source)]),
+            &Options::default().label_prefix("P1_").emit_halt(true),
+            10000,
+        )
+        .unwrap();
+        assert_eq!(emulator.top(), Some(0));
+    }
+
+    #[test]
+    fn single_token_errors_are_located() {
+        let source = "function Sys.init 0\nlabel\ngoto\nfoo\n";
+        assert_eq!(
+            translate(fixture(&[("Sys", source)]), &Options::default()),
+            Err(vec![
+                "Sys.vm:2:1 #1 'label': Missing label name argument".to_string(),
+                "Sys.vm:3:1 #2 'goto': Missing label name argument".to_string(),
+                "Sys.vm:4:1 #3 'foo': Invalid VM instruction 'foo'".to_string(),
+            ])
+        );
+    }
+
+    #[test]
+    fn files_sharing_a_name_are_rejected() {
+        let errors = translate(
+            fixture(&[("Sys", ""), ("Sys", "pop temp 0\n")]),
+            &Options::default(),
+        );
+        assert_eq!(
+            errors,
+            Err(vec![
+                "Sys.vm: more than one input file has this name, their 'Sys.N' statics would share memory"
+                    .to_string(),
+            ])
+        );
+    }
+
+    #[test]
+    fn bootstrap_file_comes_first() {
+        let file = |name: &str, source: &str, bootstrap: bool| FileInput {
+            name: name.to_string(),
+            source: source.to_string(),
+            bootstrap,
+        };
+        let main = "function Main.main 0\nreturn\n";
+        let sys = "function Sys.init 0\ncall Main.main 0\n";
+        assert_eq!(
+            translate_ordered(
+                vec![file("Main", main, false), file("Sys", sys, true)],
+                &Options::default()
+            ),
+            translate(
+                fixture(&[("Sys", sys), ("Main", main)]),
+                &Options::default()
+            )
+        );
+        assert_eq!(
+            translate_ordered(
+                vec![file("Main", main, true), file("Sys", sys, true)],
+                &Options::default()
+            ),
+            Err(vec![
+                "Main.vm: more than one file is marked as the bootstrap file".to_string(),
+                "Sys.vm: more than one file is marked as the bootstrap file".to_string(),
+            ])
+        );
+    }
+
+    #[test]
+    fn outputs_past_the_limit_are_rejected() {
+        let program = || fixture(&[("Sys", "function Sys.init 0\npush constant 1\n")]);
+        let length = count_instructions(&translate(program(), &Options::default()).unwrap());
+        let limited = |max: usize| translate(program(), &Options::default().max_instructions(max));
+        assert!(limited(length).is_ok());
+        assert_eq!(
+            limited(length - 1),
+            Err(vec![format!(
+                "the output has {} instructions, more than the limit of {}",
+                length,
+                length - 1
+            )])
+        );
+    }
+
+    #[test]
+    fn hook_rewrites_but_keeps_the_blocks() {
+        let program = || fixture(&[("Sys", "function Sys.init 0\npush constant 1\n")]);
+        let upper = |blocks: &mut Vec<String>| {
+            for x in blocks.iter_mut() {
+                *x = x.replace("// push constant 1", "// PUSH");
+            }
+        };
+        let output = translate_with_hook(&Hack, program(), &Options::default(), &upper).unwrap();
+        assert!(output.contains("// PUSH") && !output.contains("// push constant 1"));
+        let drop = |blocks: &mut Vec<String>| {
+            blocks.pop();
+        };
+        assert_eq!(
+            translate_with_hook(&Hack, program(), &Options::default(), &drop),
+            Err(vec![
+                "the hook changed the number of blocks from 2 to 1".to_string()
+            ])
+        );
+    }
+
+    #[test]
+    fn same_file_given_twice_is_told_apart() {
+        let source = "function Main.main 0\nreturn\n";
+        assert_eq!(
+            translate(fixture(&[("Main", source), ("Main", source)]), &Options::default()),
+            Err(vec![
+                "Main.vm: the same file is given more than once, its functions would be defined twice"
+                    .to_string(),
+            ])
+        );
+    }
+
+    #[test]
+    fn per_file_output_leaves_out_the_bootstrap() {
+        let program = fixture(&[
+            ("Sys", "function Sys.init 0\ncall Main.main 0\n"),
+            ("Main", "function Main.main 0\npush constant 0\nreturn\n"),
+        ]);
+        let files = translate_per_file(&Hack, program.clone(), &Options::default()).unwrap();
+        let whole = translate(program, &Options::default()).unwrap();
+        assert_eq!(
+            files.iter().map(|(x, _)| x.as_str()).collect::<Vec<&str>>(),
+            ["Sys", "Main"]
+        );
+        for (_, code) in &files {
+            assert!(!code.contains("@261"));
+            assert!(whole.contains(code.trim()));
+        }
+    }
+
+    #[test]
+    fn functions_defined_twice_are_reported() {
+        let source = "function Sys.init 0\nlabel L\ngoto L\nfunction Sys.init 0\nreturn\n";
+        assert_eq!(
+            translate(fixture(&[("Sys", source)]), &Options::default()),
+            Err(vec![
+                "Sys.vm:4:1 #3 'function Sys.init 0': label 'Sys.init' is already defined by \
+                 Sys.vm:1:1 #0 'function Sys.init 0'"
+                    .to_string()
+            ])
+        );
+    }
+
+    #[test]
+    fn missing_entry_halts_before_the_program() {
+        let program = fixture(&[(
+            "Main",
+            "function Main.main 0\npush constant 5\npop temp 0\n",
+        )]);
+        let options = Options::default().missing_entry(MissingEntry::Halt);
+        let emulator = crate::run_vm(program, &options, 1000).unwrap();
+        assert!(emulator.halted());
+        assert_eq!(emulator.ram()[0], 261);
+        assert_eq!(emulator.ram()[5], 0);
+    }
+
+    #[test]
+    fn labels_shadowing_predefined_symbols_are_rejected() {
+        let error = |source: &str| translate(fixture(&[("Main", source)]), &Options::default());
+        assert_eq!(
+            error("function SP 0\nreturn\n"),
+            Err(vec![
+                "Main.vm:1:10 #0 'function SP 0': label 'SP' shadows a predefined symbol"
+                    .to_string()
+            ])
+        );
+        assert_eq!(
+            error("function Main.main 0\ncall R13 0\n"),
+            Err(vec![
+                "Main.vm:2:6 #1 'call R13 0': label 'R13' shadows a predefined symbol".to_string()
+            ])
+        );
+        assert_eq!(
+            error("function SCREEN 0\nreturn\n"),
+            Err(vec![
+                "Main.vm:1:10 #0 'function SCREEN 0': label 'SCREEN' shadows a predefined symbol"
+                    .to_string()
+            ])
+        );
+        assert!(error("function Foo.SP 0\nreturn\n").is_ok());
+    }
+
+    #[test]
+    fn surplus_arguments_are_rejected() {
+        let source = "function Sys.init 0\npush constant 7 8\nadd 5\npop local 0 1\n";
+        assert_eq!(
+            translate(fixture(&[("Sys", source)]), &Options::default()),
+            Err(vec![
+                "Sys.vm:2:17 #1 'push constant 7 8': 'push' takes 2 arguments, got 3".to_string(),
+                "Sys.vm:3:5 #2 'add 5': 'add' takes 0 arguments, got 1".to_string(),
+                "Sys.vm:4:13 #3 'pop local 0 1': 'pop' takes 2 arguments, got 3".to_string(),
+            ])
+        );
+    }
+
+    #[test]
+    fn function_names_split_by_whitespace_are_rejected() {
+        let split = ", function names can't contain whitespace";
+        let main = "function Main.main 0\ncall Foo.bar baz 1\nreturn\n";
+        assert_eq!(
+            translate(fixture(&[("Main", main)]), &Options::default()),
+            Err(vec![format!(
+                "Main.vm:2:14 #1 'call Foo.bar baz 1': 'call' takes 2 arguments, got 3{}",
+                split
+            )])
+        );
+        // across files, each error is reported against the file it is in
+        let foo = "function Foo.bar baz 3\npush constant 0\nreturn\n";
+        assert_eq!(
+            translate(
+                fixture(&[("Main", main), ("Foo", foo)]),
+                &Options::default().entry("Main.main")
+            ),
+            Err(vec![
+                format!(
+                    "Main.vm:2:14 #1 'call Foo.bar baz 1': 'call' takes 2 arguments, got 3{}",
+                    split
+                ),
+                format!(
+                    "Foo.vm:1:18 #0 'function Foo.bar baz 3': 'function' takes 2 arguments, got 3{}",
+                    split
+                ),
+            ])
+        );
+    }
+
+    #[test]
+    fn fail_fast_stops_at_the_first_error() {
+        let source = "function Sys.init 0\npop constant 1\nfoo\npush local x\n";
+        let errors =
+            |options: &Options| translate(fixture(&[("Sys", source)]), options).unwrap_err();
+        assert_eq!(errors(&Options::default()).len(), 3);
+        assert_eq!(
+            errors(&Options::default().fail_fast(true)),
+            ["Sys.vm:2:5 #1 'pop constant 1': Invalid segment argument 'constant'"]
+        );
+    }
+
+    /// Returns the translation of a program using the unknown `frob` opcode
+    fn with_unknown_opcode(mode: UnknownOpcode) -> Result<String, Vec<String>> {
+        let source = "function Sys.init 0\nfrob 3\npush constant 1\n";
+        translate(
+            fixture(&[("Sys", source)]),
+            &Options::default().unknown_opcode(mode),
+        )
+    }
+
+    #[test]
+    fn unknown_opcode_is_an_error_by_default() {
+        assert_eq!(
+            with_unknown_opcode(UnknownOpcode::Error),
+            Err(vec![
+                "Sys.vm:2:1 #1 'frob 3': Invalid VM instruction 'frob'".to_string()
+            ])
+        );
+    }
+
+    #[test]
+    fn unknown_opcode_skipped() {
+        let output = with_unknown_opcode(UnknownOpcode::Skip).unwrap();
+        assert!(!output.contains("frob"));
+        assert!(output.contains("// push constant 1"));
+    }
+
+    #[test]
+    fn unknown_opcode_commented() {
+        let output = with_unknown_opcode(UnknownOpcode::Comment).unwrap();
+        assert!(output.contains("\n// unknown: frob 3\n"));
+        assert!(output.contains("// push constant 1"));
+    }
+
+    #[test]
+    fn leading_bom_is_ignored() {
+        let source = "function Sys.init 0\npush constant 1\n";
+        let with_bom = format!("\u{feff}{}", source);
+        assert_eq!(
+            translate(fixture(&[("Sys", &with_bom)]), &Options::default()),
+            translate(fixture(&[("Sys", source)]), &Options::default())
+        );
+        assert_eq!(
+            parse_contents(&with_bom),
+            [(1, "function Sys.init 0"), (2, "push constant 1")]
+        );
+        assert_eq!(
+            normalize(&with_bom),
+            "function Sys.init 0\npush constant 1\n"
+        );
+    }
+
+    #[test]
+    fn markdown_blocks_are_extracted_and_translated() {
+        let markdown = "# Adding\n\nFirst the entry point:\n\n<!--vm\nfunction Sys.init 0\n\
+                        vm-->\n\nThen the sum, `2 + 3`:\n\n<!--vm\npush constant 2\npush constant 3\n\
+                        add\nvm-->\n";
+        let source = extract_markers(markdown).unwrap();
+        assert_eq!(
+            normalize(&source),
+            "function Sys.init 0\npush constant 2\npush constant 3\nadd\n"
+        );
+        let program = fixture(&[("Sys", &source)]);
+        assert_eq!(
+            translate(program, &Options::default()),
+            translate(
+                fixture(&[(
+                    "Sys",
+                    "function Sys.init 0\npush constant 2\npush constant 3\nadd\n"
+                )]),
+                &Options::default()
+            )
+        );
+        assert_eq!(
+            extract_markers("<!--vm\npush constant 1\n"),
+            Err("Unterminated '<!--vm' block, expected a closing 'vm-->'".to_string())
+        );
+    }
+
+    #[test]
+    fn uncalled_functions_are_stripped() {
+        let sys = "function Sys.init 0\ncall Main.main 0\nlabel end\ngoto end\n";
+        let main = "function Main.main 0\ncall Main.helper 0\nreturn\n\
+                    function Main.helper 0\npush constant 0\nreturn\n\
+                    function Main.unused 0\nlabel loop\ngoto loop\n";
+        let output = translate(
+            fixture(&[("Sys", sys), ("Main", main)]),
+            &Options::default().strip_unused_functions(true),
+        )
+        .unwrap();
+        assert!(output.contains("(Main.main)") && output.contains("(Main.helper)"));
+        assert!(!output.contains("Main.unused"));
+    }
+
+    #[test]
+    fn return_labels_are_unique_across_calls_and_files() {
+        let mut return_labels = ReturnLabelAllocator::default();
+        let allocated: Vec<String> = (0..100)
+            .map(|_| return_labels.allocate("Main.main"))
+            .collect();
+        let mut unique = allocated.clone();
+        unique.sort();
+        unique.dedup();
+        assert_eq!(unique.len(), allocated.len());
+        let calls = "call Sys.f 0\n".repeat(20);
+        let main = format!("function Main.main 0\n{}return\n", calls);
+        let sys = format!(
+            "function Sys.init 0\n{}call Main.main 0\nlabel end\ngoto end\n\
+             function Sys.f 0\npush constant 0\nreturn\n",
+            calls
+        );
+        let code = translate(
+            fixture(&[("Main", &main), ("Sys", &sys)]),
+            &Options::default(),
+        );
+        let code = code.unwrap();
+        let mut labels: Vec<&str> = defined_labels(&code)
+            .into_iter()
+            .filter(|x| x.contains("$ret."))
+            .collect();
+        // 20 calls in each file and the call of Main.main
+        assert_eq!(labels.len(), 41);
+        labels.sort();
+        labels.dedup();
+        assert_eq!(labels.len(), 41);
+    }
+
+    #[test]
+    fn blocks_follow_their_line_directives() {
+        let source = "// Sys.vm\nfunction Sys.init 0\n\npush constant 1\n// sum\nadd\n";
+        let program = fixture(&[("Sys", source)]);
+        let code = translate(program, &Options::default().include_line_directives(true)).unwrap();
+        let lines: Vec<&str> = code.lines().collect();
+        for (line, raw) in [
+            (2, "function Sys.init 0"),
+            (4, "push constant 1"),
+            (6, "add"),
+        ] {
+            let directive = format!("// #line {} Sys.vm", line);
+            let i = lines.iter().position(|x| *x == directive).unwrap();
+            assert_eq!(lines[i + 1], format!("// {}", raw));
+        }
+        assert_eq!(code.matches("// #line ").count(), 3);
+    }
+
+    #[test]
+    fn trace_annotates_the_stack_deltas() {
+        let source = "function Sys.init 0\npush constant 1\npush constant 2\nadd\n";
+        let program = fixture(&[("Sys", source)]);
+        let code = translate(program, &Options::default().trace(true)).unwrap();
+        let deltas: Vec<&str> = code
+            .lines()
+            .filter(|x| x.starts_with("// stack: "))
+            .collect();
+        assert_eq!(
+            deltas,
+            [
+                "// stack: +0",
+                "// stack: +1",
+                "// stack: +1",
+                "// stack: -1"
+            ]
+        );
+    }
+
+    #[test]
+    fn errors_name_their_file() {
+        let program = fixture(&[
+            ("Main", "function Main.main 0\npush constant 1\nreturn\n"),
+            ("Sys", "function Sys.init 0\npush local x\n"),
+        ]);
+        let errors = translate(program, &Options::default()).unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].starts_with("Sys.vm:2:"), "{}", errors[0]);
+        assert!(!errors[0].contains("Main.vm"));
+    }
+
+    #[test]
+    fn banner_lists_the_input_files() {
+        let program = fixture(&[
+            ("Main", "function Main.main 0\npush constant 1\nreturn\n"),
+            ("Sys", "function Sys.init 0\ncall Main.main 0\n"),
+        ]);
+        let code = translate(program, &Options::default().banner(true)).unwrap();
+        let banner: Vec<&str> = code.lines().take_while(|x| !x.is_empty()).collect();
+        assert!(banner.iter().all(|x| x.starts_with("//")));
+        assert!(banner.contains(&"// Input files: Main.vm, Sys.vm"));
+        assert!(banner.contains(&"// Instructions: 5"));
+    }
+
+    #[test]
+    fn file_markers_precede_the_files_code() {
+        let program = fixture(&[
+            ("Sys", "function Sys.init 0\ncall Main.main 0\n"),
+            ("Main", "function Main.main 0\npush constant 1\nreturn\n"),
+        ]);
+        let code = translate(program, &Options::default().sections(true)).unwrap();
+        let lines: Vec<&str> = code.lines().collect();
+        let position = |line: &str| lines.iter().position(|x| *x == line).unwrap();
+        assert!(position("// ===== Bootstrap =====") < position("// ===== File: Sys.vm ====="));
+        let main = position("// ===== File: Main.vm =====");
+        assert_eq!(lines[main + 1], "// ----- function Main.main -----");
+        assert_eq!(lines[main + 2], "// function Main.main 0");
+        assert!(position("// call Main.main 0") < main);
+        assert_eq!(code.matches("// =====").count(), 3);
+    }
+
+    #[test]
+    fn differently_formatted_sources_normalize_alike() {
+        let tidy = "function Main.f 1\npush local 0\nadd\nreturn\n";
+        let messy = "// Main.vm\n\n  function   Main.f\t1\npush local 0 // the only local\n\n\t add  \r\nreturn";
+        assert_eq!(normalize(messy), normalize(tidy));
+        assert_eq!(normalize(tidy), tidy);
+    }
+
+    #[test]
+    fn parse_yields_the_instructions_in_order() {
+        let source = "// Main.vm\nfunction Main.f 0\n\n  push constant 7 // seven\nreturn\n";
+        let instructions: Vec<Instruction> = parse("Main", source).map(Result::unwrap).collect();
+        let parsed: Vec<_> = instructions
+            .iter()
+            .map(|x| (x.operation, x.arg1, x.arg2, x.line, x.column))
+            .collect();
+        assert_eq!(
+            parsed,
+            [
+                ("function", Some("Main.f"), Some("0"), 2, 1),
+                ("push", Some("constant"), Some("7"), 4, 3),
+                ("return", None, None, 5, 1),
+            ]
+        );
+        assert!(instructions.iter().all(|x| x.frame.is_none()));
+    }
+
+    #[test]
+    fn bootstrap_pushes_the_entry_arguments_in_order() {
+        let program = fixture(&[("Foo", "function Foo.bar 0\npush argument 1\nreturn\n")]);
+        let options = Options::default()
+            .entry("Foo.bar")
+            .entry_args(vec![3u16, 5]);
+        let code = translate(program.clone(), &options).unwrap();
+        let lines = code_lines(&code);
+        let position = |line: &str| lines.iter().position(|x| *x == line).unwrap();
+        assert!(position("@3") < position("@5"));
+        assert!(position("@5") < position("@Foo.bar"));
+        assert!(position("@Foo.bar") < position("(Foo.bar)"));
+        // argument 1 is the second constant, returned in place of the arguments
+        let emulator = crate::run_vm(program, &options, 10_000).unwrap();
+        assert!(emulator.halted());
+        assert_eq!(emulator.ram()[0], 257);
+        assert_eq!(emulator.ram()[256], 5);
+    }
+
+    #[test]
+    fn annotated_frames_keep_the_instructions() {
+        let source = "function Sys.init 0\ncall Sys.f 0\nlabel end\ngoto end\n\
+                  function Sys.f 0\npush constant 0\nreturn\n";
+        let program = fixture(&[("Sys", source)]);
+        let plain = translate(program.clone(), &Options::default()).unwrap();
+        let annotated = translate(program, &Options::default().annotate_frames(true)).unwrap();
+        assert_eq!(code_lines(&annotated), code_lines(&plain));
+        for comment in [
+            "// push the return address",
+            "// save the caller's THAT",
+            "// restore the caller's THAT, 1 slot below LCL",
+            "// restore the caller's LCL",
+            "// jump to the saved return address",
+        ] {
+            assert!(annotated.lines().any(|x| x == comment), "{}", comment);
+            assert!(!plain.contains(comment));
+        }
+    }
+
+    #[test]
+    fn compact_output_has_no_blank_lines() {
+        let source = "function Sys.init 0\npush constant 1\npush constant 2\nlt\n\
+                  call Sys.f 0\nlabel end\ngoto end\nfunction Sys.f 0\npush constant 0\nreturn\n";
+        let program = fixture(&[("Sys", source)]);
+        let options = Options::default().banner(true).sections(true);
+        let spaced = translate(program.clone(), &options).unwrap();
+        assert!(spaced.contains("\n\n"));
+        let compact = translate(program, &options.compact(true)).unwrap();
+        assert!(!compact.contains("\n\n"));
+        assert_eq!(code_lines(&compact), code_lines(&spaced));
+    }
+
+    #[test]
+    fn multiple_files_without_sys_init_are_rejected() {
+        let program = fixture(&[
+            ("Main", "function Main.main 0\npush constant 1\nreturn\n"),
+            ("Math", "function Math.double 0\npush argument 0\nreturn\n"),
+        ]);
+        assert_eq!(
+            translate(program.clone(), &Options::default()),
+            Err(vec![
+                "bootstrap requires a Sys.init function, none found".to_string()
+            ])
+        );
+        // a single file is translated without the bootstrap's call
+        assert!(translate(program[..1].to_vec(), &Options::default()).is_ok());
+    }
+
+    #[test]
+    fn strict_asm_has_one_instruction_per_line() {
+        let source = "// Sys.vm\nfunction Sys.init 0\npush constant 1\npush constant 2\nlt\n\
+                  call Sys.f 0\nlabel end\ngoto end\nfunction Sys.f 0\npush constant 0\nreturn\n";
+        let program = fixture(&[("Sys", source)]);
+        let options = Options::default()
+            .strict_asm(true)
+            .banner(true)
+            .sections(true)
+            .annotate_frames(true)
+            .emit_halt(true);
+        let code = translate(program, &options).unwrap();
+        assert!(code.ends_with('\n') && !code.ends_with("\n\n"));
+        for line in code.lines() {
+            assert!(!line.is_empty() && !line.contains("//"), "{:?}", line);
+            assert!(!line.contains(char::is_whitespace), "{:?}", line);
+            assert!(
+                crate::validate::validate_assembly(line).is_ok(),
+                "{:?}",
+                line
+            );
+        }
+    }
+
+    #[test]
+    fn translating_twice_gives_identical_output() {
+        let program = fixture(&[
+            (
+                "Sys",
+                "function Sys.init 0\ncall Main.main 0\nlabel end\ngoto end\n",
+            ),
+            (
+                "Main",
+                "function Main.main 2\npush static 3\ndup\nswap\nadd\npop local 0\n\
+             call Math.unknown 0\nreturn\nfunction Main.unused 0\nreturn\n",
+            ),
+        ]);
+        let table = "dup = \"@SP\\nA=M-1\\nD=M\"\nswap = \"@SP\\nA=M-1\\nD=M // {id}\"\n";
+        let options = Options::default()
+            .opcode_table(opcodes::parse_opcode_table(table).unwrap())
+            .external_functions(vec!["Math.unknown".to_string()])
+            .banner(true)
+            .profile(true)
+            .stable_labels(true)
+            .optimize(true);
+        let reports = || {
+            let instructions = parse_program(&program);
+            (
+                translate(program.clone(), &options),
+                translate_per_file(&Hack, program.clone(), &options),
+                lint::lint(&program, &options),
+                format!("{:?}", analysis::defined_functions(&instructions)),
+            )
+        };
+        let first = reports();
+        assert!(first.0.is_ok() && first.1.is_ok(), "{:?}", first);
+        assert_eq!(first, reports());
+    }
+
+    #[test]
+    fn raw_leaves_out_trailing_comments() {
+        let source = "push constant 7 // the seven\n  add//sum\n// only a comment\n";
+        let raws: Vec<&str> = parse("Main", source).map(|x| x.unwrap().raw).collect();
+        assert_eq!(raws, ["push constant 7", "add"]);
+        let program = fixture(&[("Main", source)]);
+        let code = translate(program, &Options::default()).unwrap();
+        assert!(code.contains("// push constant 7\n") && !code.contains("the seven"));
+    }
+
+    #[test]
+    fn external_entry_needs_no_definition() {
+        let program = fixture(&[
+            ("Main", "function Main.main 0\ncall Lib.helper 0\nreturn\n"),
+            ("Util", "function Util.f 0\ncall Lib.helper 0\nreturn\n"),
+        ]);
+        let options = Options::default().entry("Lib.start");
+        assert_eq!(
+            translate(program.clone(), &options),
+            Err(vec![
+                "bootstrap requires a Lib.start function, none found".to_string()
+            ])
+        );
+        let external = options.external_functions(vec!["Lib.start".to_string()]);
+        let code = translate(program, &external).unwrap();
+        assert!(code.contains("@Lib.start\n0;JMP") && !code.contains("(Lib.start)"));
+    }
+
+    #[test]
+    fn traced_templates_name_their_files() {
+        let source = "function Sys.init 0\npush constant 7\npop local 0\n";
+        let program = fixture(&[("Sys", source)]);
+        let code = translate(program, &Options::default().trace_templates(true)).unwrap();
+        assert!(code.contains(
+            "// push constant 7\n// template: push/constant.asm\n// template: push/main.asm\n@7\n"
+        ));
+        assert!(code.contains("// pop local 0\n// template: pop/segment_full.asm\n@LCL\n"));
+    }
+
+    #[test]
+    fn header_follows_the_bootstrap_and_footer_ends_the_output() {
+        let program = fixture(&[("Sys", "function Sys.init 0\nlabel end\ngoto end\n")]);
+        let options = Options::default()
+            .asm_header("// stubs\n(TRAP)\n@TRAP\n0;JMP")
+            .asm_footer("(DATA)\n@DATA\n")
+            .emit_halt(true);
+        let code = translate(program, &options).unwrap();
+        assert!(code.contains("@Sys.init\n0;JMP\n// stubs\n(TRAP)\n@TRAP\n0;JMP\n"));
+        assert!(code.find("(TRAP)") < code.find("(Sys.init)"));
+        assert!(code.ends_with("(DATA)\n@DATA\n"));
+    }
+}
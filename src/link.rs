@@ -0,0 +1,88 @@
+use std::collections::{HashMap, HashSet};
+
+/// Replaces the references to the labels defined in the assembly with the ROM
+/// addresses they stand for, as the first pass of the assembler would resolve them.
+/// Other symbols, such as variables and predefined symbols, are left alone
+pub fn resolve_labels(code: &str) -> String {
+    let mut addresses = HashMap::new();
+    let mut address = 0;
+    for line in code.lines().map(|x| x.trim()) {
+        if let Some(label) = label(line) {
+            addresses.insert(label, address);
+        } else if !(line.is_empty() || line.starts_with("//")) {
+            address += 1;
+        }
+    }
+    code.lines()
+        .map(|line| match line.trim().strip_prefix('@') {
+            Some(symbol) if addresses.contains_key(symbol) => format!("@{}", addresses[symbol]),
+            _ => line.to_string(),
+        })
+        .collect::<Vec<String>>()
+        .join("\n")
+        + "\n"
+}
+
+/// Returns the label defined by the line, if it is a label definition
+fn label(line: &str) -> Option<&str> {
+    line.strip_prefix('(')?.strip_suffix(')')
+}
+
+/// Renames the labels defined in the assembly to short names, `L0`, `L1`, ...
+/// in the order they are defined, along with the references to them. Names
+/// already used by other symbols are skipped, and comments are left alone
+pub fn minify_labels(code: &str) -> String {
+    let lines = code.lines().map(|x| x.trim()).collect::<Vec<&str>>();
+    let defined = lines.iter().filter_map(|x| label(x)).collect::<Vec<&str>>();
+    let symbols = lines
+        .iter()
+        .filter_map(|x| x.strip_prefix('@'))
+        .filter(|x| !defined.contains(x))
+        .collect::<HashSet<&str>>();
+    let mut names = HashMap::new();
+    let mut next = 0;
+    for x in defined {
+        let name = loop {
+            let name = format!("L{}", next);
+            next += 1;
+            if !symbols.contains(name.as_str()) {
+                break name;
+            }
+        };
+        names.entry(x).or_insert(name);
+    }
+    code.lines()
+        .map(|line| {
+            let trimmed = line.trim();
+            if let Some(name) = label(trimmed).and_then(|x| names.get(x)) {
+                format!("({})", name)
+            } else if let Some(name) = trimmed.strip_prefix('@').and_then(|x| names.get(x)) {
+                format!("@{}", name)
+            } else {
+                line.to_string()
+            }
+        })
+        .collect::<Vec<String>>()
+        .join("\n")
+        + "\n"
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resolve_labels_counts_only_instructions() {
+        let code = "// start\n(LOOP)\n@LOOP\n\n0;JMP\n(END)\n@END\n@counter\n";
+        assert_eq!(
+            resolve_labels(code),
+            "// start\n(LOOP)\n@0\n\n0;JMP\n(END)\n@2\n@counter\n"
+        );
+    }
+
+    #[test]
+    fn minify_labels_skips_names_in_use() {
+        let code = "(Main.main$loop)\n@L0\nM=0\n@Main.main$loop\n0;JMP\n(Main.main$end)\n";
+        assert_eq!(minify_labels(code), "(L1)\n@L0\nM=0\n@L1\n0;JMP\n(L2)\n");
+    }
+}
@@ -0,0 +1,271 @@
+use crate::backend::scratch_registers;
+use crate::command::parse_index;
+use crate::{
+    defined_functions, parse_contents, parse_program, stack_delta, strip_bom, Instruction, Options,
+    Segment,
+};
+
+/// Formats a warning about the given instruction, located like translation errors
+/// at the token the message quotes
+fn warning(instruction: &Instruction, message: String) -> String {
+    format!(
+        "{}.vm:{}:{} #{} '{}': warning: {}",
+        instruction.file,
+        instruction.line,
+        instruction.error_column(&message),
+        instruction.id,
+        instruction.raw,
+        message
+    )
+}
+
+/// Warns about static indices above the configured threshold,
+/// which usually are typos as they approach the end of Hack's static space
+fn check_static_threshold(instruction: &Instruction, options: &Options) -> Option<String> {
+    let index = match instruction {
+        Instruction {
+            operation: "push" | "pop",
+            arg1: Some("static"),
+            arg2: Some(index),
+            ..
+        } => parse_index(Segment::Static, index).ok()?,
+        _ => None?,
+    };
+    (index > options.static_threshold).then(|| {
+        warning(
+            instruction,
+            format!(
+                "static index {} is above the threshold of {}",
+                index, options.static_threshold
+            ),
+        )
+    })
+}
+
+/// Warns about temp indices mapping to a register the generated code uses as scratch,
+/// whose value the next return or pop through it overwrites
+fn check_scratch_temp(instruction: &Instruction, options: &Options) -> Option<String> {
+    let index = match instruction {
+        Instruction {
+            operation: "push" | "pop",
+            arg1: Some("temp"),
+            arg2: Some(index),
+            ..
+        } => parse_index(Segment::Temp, index).ok()?,
+        _ => None?,
+    };
+    let register = index.checked_add(5)?;
+    let users = scratch_registers(options)
+        .into_iter()
+        .filter(|(r, _)| *r == register)
+        .map(|(_, user)| user)
+        .collect::<Vec<&str>>();
+    (!users.is_empty()).then(|| {
+        warning(
+            instruction,
+            format!(
+                "temp {} maps to R{}, which is used as scratch by {}",
+                index,
+                register,
+                users.join(" and ")
+            ),
+        )
+    })
+}
+
+/// Warns about functions without any return before the next function,
+/// which fall through into it unless they end in an infinite loop
+fn check_missing_returns(instructions: &[Instruction]) -> Vec<String> {
+    defined_functions(instructions)
+        .into_iter()
+        .filter_map(|info| {
+            let function = &instructions[info.span.start];
+            let body = &instructions[info.span.start + 1..info.span.end];
+            let returns = body.iter().any(|x| x.operation == "return");
+            let loops = body.last().is_some_and(|x| x.operation == "goto");
+            (!returns && !loops).then(|| {
+                warning(
+                    function,
+                    format!("function '{}' has no return and falls through", info.name),
+                )
+            })
+        })
+        .collect()
+}
+
+/// Warns about functions declaring more locals than they access, by more than
+/// the configured slack, which wastes stack space and may be a miscount
+fn check_unused_locals(instructions: &[Instruction], options: &Options) -> Vec<String> {
+    defined_functions(instructions)
+        .into_iter()
+        .filter_map(|info| {
+            let used = instructions[info.span.clone()]
+                .iter()
+                .filter(|x| matches!(x.operation, "push" | "pop") && x.arg1 == Some("local"))
+                .filter_map(|x| parse_index(Segment::Local, x.arg2?).ok())
+                .map(|x| x as usize + 1)
+                .max()
+                .unwrap_or(0);
+            (info.locals > used + options.local_slack).then(|| {
+                warning(
+                    &instructions[info.span.start],
+                    match used {
+                        0 => format!(
+                            "function '{}' declares {} locals but never accesses them",
+                            info.name, info.locals
+                        ),
+                        n => format!(
+                            "function '{}' declares {} locals but accesses none above local {}",
+                            info.name,
+                            info.locals,
+                            n - 1
+                        ),
+                    },
+                )
+            })
+        })
+        .collect()
+}
+
+/// Warns about the 2-operand arithmetic & logical instructions (add, sub, and, or) preceded
+/// by fewer than two net pushes since the last label or function, which pop values
+/// the straight-line code before them never pushed. Checking stops at a return,
+/// after which the stack height is unknown until the next label or function
+fn check_binop_operands(instructions: &[Instruction]) -> Vec<String> {
+    let mut warnings = vec![];
+    let mut height = Some(0);
+    for (i, x) in instructions.iter().enumerate() {
+        let boundary = matches!(x.operation, "label" | "function")
+            || i.checked_sub(1)
+                .is_some_and(|j| instructions[j].file != x.file);
+        if boundary {
+            height = Some(0);
+        }
+        if let (Some(h), "add" | "sub" | "and" | "or") = (height, x.operation) {
+            if h < 2 {
+                let h = h.max(0);
+                warnings.push(warning(
+                    x,
+                    format!(
+                        "'{}' pops two operands, but only {} value{} pushed since the last label or function",
+                        x.operation,
+                        h,
+                        if h == 1 { " was" } else { "s were" }
+                    ),
+                ));
+            }
+        }
+        // the locals a function pushes are part of its frame, not operands
+        if x.operation != "function" {
+            height = height.zip(stack_delta(x)).map(|(h, d)| h + d);
+        }
+    }
+    warnings
+}
+
+/// Warns about files without any instructions, telling apart files
+/// without any content from files containing only comments
+fn check_empty_files(contents: &[(String, String)]) -> Vec<String> {
+    contents
+        .iter()
+        .filter(|(_, c)| parse_contents(c).is_empty())
+        .map(|(file, c)| match strip_bom(c).trim().is_empty() {
+            true => format!("warning: {}.vm is empty", file),
+            false => format!("warning: {}.vm contains only comments/whitespace", file),
+        })
+        .collect()
+}
+
+/// Given a slice of tuples of a VM filename and its contents,
+/// returns warnings about suspicious but valid code
+pub fn lint(contents: &[(String, String)], options: &Options) -> Vec<String> {
+    let instructions = parse_program(contents);
+    let mut warnings = check_empty_files(contents);
+    warnings.extend(
+        instructions
+            .iter()
+            .filter_map(|x| check_static_threshold(x, options)),
+    );
+    warnings.extend(
+        instructions
+            .iter()
+            .filter_map(|x| check_scratch_temp(x, options)),
+    );
+    warnings.extend(check_missing_returns(&instructions));
+    warnings.extend(check_binop_operands(&instructions));
+    warnings.extend(check_unused_locals(&instructions, options));
+    warnings
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Lints the source as the only file, `Main.vm`
+    fn lint_main(source: &str, options: &Options) -> Vec<String> {
+        lint(&[("Main".to_string(), source.to_string())], options)
+    }
+
+    #[test]
+    fn static_above_threshold_is_located() {
+        let warnings = lint_main(
+            "function Main.f 0\n  push static 250\nreturn\n",
+            &Options::default(),
+        );
+        assert_eq!(
+            warnings,
+            ["Main.vm:2:3 #1 'push static 250': warning: static index 250 is above the threshold of 240"]
+        );
+    }
+
+    #[test]
+    fn missing_return_points_at_the_function_name() {
+        let warnings = lint_main("function Main.f 0\npush constant 1\n", &Options::default());
+        assert_eq!(
+            warnings,
+            ["Main.vm:1:10 #0 'function Main.f 0': warning: function 'Main.f' has no return and falls through"]
+        );
+    }
+
+    #[test]
+    fn binop_after_a_single_push() {
+        let warnings = lint_main(
+            "function Main.f 0\npush constant 5\nadd\nreturn\n",
+            &Options::default(),
+        );
+        assert_eq!(
+            warnings,
+            ["Main.vm:3:1 #2 'add': warning: 'add' pops two operands, but only 1 value was pushed since the last label or function"]
+        );
+    }
+
+    #[test]
+    fn scratch_temp_and_unused_locals() {
+        // temp indices past 7 are rejected, so only a scratch register moved into the
+        // temp segment through the library can be aliased
+        let warnings = lint_main(
+            "function Main.f 4\npush temp 7\nreturn\n",
+            &Options::default().pop_scratch(12u16),
+        );
+        assert_eq!(warnings.len(), 2);
+        assert!(
+            warnings[0].starts_with("Main.vm:2:1 #1 'push temp 7': warning: temp 7 maps to R12")
+        );
+        assert!(warnings[1]
+            .ends_with("warning: function 'Main.f' declares 4 locals but never accesses them"));
+        assert!(lint_main("function Main.f 2\nreturn\n", &Options::default()).is_empty());
+        let strict = Options::default().local_slack(0usize);
+        assert_eq!(lint_main("function Main.f 2\nreturn\n", &strict).len(), 1);
+    }
+
+    #[test]
+    fn empty_files_are_told_from_comment_only_files() {
+        let options = Options::default();
+        assert_eq!(lint_main("", &options), ["warning: Main.vm is empty"]);
+        assert_eq!(lint_main("\n  \n", &options), ["warning: Main.vm is empty"]);
+        assert_eq!(
+            lint_main("// comment\n// another\n", &options),
+            ["warning: Main.vm contains only comments/whitespace"]
+        );
+    }
+}
@@ -0,0 +1,73 @@
+use std::fmt;
+use std::sync::RwLock;
+
+/// How important a diagnostic is
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Level {
+    Error,
+    Warn,
+    Info,
+}
+
+impl fmt::Display for Level {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Level::Error => "error",
+            Level::Warn => "warning",
+            Level::Info => "info",
+        })
+    }
+}
+
+/// Receives the diagnostics of the translation
+type Logger = Box<dyn Fn(Level, &str) + Send + Sync>;
+
+static LOGGER: RwLock<Option<Logger>> = RwLock::new(None);
+
+/// Installs the function receiving the diagnostics of the translation, like the number
+/// of instructions of each file, replacing the previous one. Without a logger they are dropped
+pub fn set_logger(logger: impl Fn(Level, &str) + Send + Sync + 'static) {
+    *LOGGER.write().unwrap() = Some(Box::new(logger));
+}
+
+/// Passes the diagnostic to the installed logger, if any
+pub(crate) fn emit(level: Level, message: &str) {
+    if let Some(logger) = LOGGER.read().unwrap().as_ref() {
+        logger(level, message);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{translate, Options};
+    use std::sync::Mutex;
+
+    #[test]
+    fn translation_logs_the_counts() {
+        static LOGGED: Mutex<Vec<String>> = Mutex::new(vec![]);
+        set_logger(|level, message| {
+            LOGGED
+                .lock()
+                .unwrap()
+                .push(format!("{}: {}", level, message))
+        });
+        let program = vec![
+            (
+                "Logged".to_string(),
+                "function Logged.f 0\nreturn\n".to_string(),
+            ),
+            (
+                "Sys".to_string(),
+                "function Sys.init 0\ncall Logged.f 0\n".to_string(),
+            ),
+        ];
+        translate(program, &Options::default()).unwrap();
+        let logged = LOGGED.lock().unwrap();
+        assert!(logged.contains(&"info: Logged.vm: 2 instructions".to_string()));
+        assert!(logged.contains(&"info: Sys.vm: 2 instructions".to_string()));
+        assert!(logged
+            .iter()
+            .any(|x| x.starts_with("info: translated 4 instructions into ")));
+    }
+}
@@ -1,391 +1,1063 @@
+use std::collections::{HashMap, HashSet};
 use std::env;
 use std::fs;
-use std::path::Path;
+use std::io::{self, IsTerminal, Write};
+use std::path::{Component, Path, PathBuf};
+use vmtranslator::{
+    count_instructions, diff, explain, extract_markers, lint, normalize, parse, parse_opcode_table,
+    translate_html, translate_line, translate_per_file, translate_with, unresolved_calls, Backend,
+    Dialect, Hack, Instruction, MissingEntry, Options, Stub, TranslateContext, UnknownOpcode,
+};
 
-/// A VM instruction is represented here
-#[derive(Clone)]
-struct Instruction<'a> {
-    operation: &'a str,
-    arg1: Option<&'a str>,
-    arg2: Option<&'a str>,
-    raw: &'a str,
-    file: &'a str,
-    id: usize,
-    frame: Option<&'a str>,
+/// When to color the messages printed to the terminal
+#[derive(Clone, Copy, Default)]
+enum ColorChoice {
+    #[default]
+    Auto,
+    Always,
+    Never,
 }
 
-impl<'a> Instruction<'a> {
-    /// Given an instruction string (with whitespaces and comments removed),
-    /// returns a new Instruction
-    fn new(s: &'a str, id: usize, file: &'a str) -> Result<Self, &'static str> {
-        let mut parts = s.split(" ");
-        Ok(Self {
-            raw: s,
-            operation: parts.next().ok_or("Unable to parse empty line")?,
-            arg1: parts.next(),
-            arg2: parts.next(),
-            file,
-            id,
-            frame: None,
-        })
+impl ColorChoice {
+    /// Returns whether output to a stream should be colored,
+    /// given whether that stream is a terminal
+    fn enabled(self, is_terminal: bool) -> bool {
+        match self {
+            ColorChoice::Auto => is_terminal && env::var_os("NO_COLOR").is_none(),
+            ColorChoice::Always => true,
+            ColorChoice::Never => false,
+        }
     }
+}
 
-    /// Given a vector of instructions, set the frame field of self
-    fn set_frame(&mut self, instructions: &Vec<Instruction<'a>>) {
-        let get_frame = || match self.operation {
-            "operation" => self.arg1,
-            _ => {
-                instructions
-                    .iter()
-                    .filter(|x| x.operation == "function")
-                    .filter(|x| x.id < self.id)
-                    .last()?
-                    .arg1
-            }
-        };
-        self.frame = get_frame();
+/// Wraps the message in the given ANSI color code if enabled
+fn paint(message: &str, code: &str, enabled: bool) -> String {
+    match enabled {
+        true => format!("\x1b[{}m{}\x1b[0m", code, message),
+        false => message.to_string(),
     }
 }
 
-/// Parses the program contents into a vector of instructions
-/// with whitespaces and comments removed
-fn parse_contents(contents: &str) -> Vec<&str> {
-    contents
-        .lines()
-        .map(|x| x.split("//").next().unwrap().trim())
-        .filter(|x| !(x.is_empty()))
-        .collect()
+/// The newline style of the written assembly
+#[derive(Clone, Copy, Default)]
+enum LineEnding {
+    #[default]
+    Lf,
+    Crlf,
 }
 
-/// This represents a memmory operation type
-/// Push / Pop
-#[derive(Clone, Copy)]
-enum MemOpType {
-    Push,
-    Pop,
+impl LineEnding {
+    /// Returns the code with its newlines in this style
+    fn apply(self, code: String) -> String {
+        match self {
+            LineEnding::Lf => code,
+            LineEnding::Crlf => code.replace('\n', "\r\n"),
+        }
+    }
 }
 
-/// Return the formatted code for a general segment push/pop VM instruction
-/// (segments: argument, local, this, that)
-fn segment_fmt(opt: MemOpType, instruction: &Instruction) -> Result<String, String> {
-    let segment = match instruction.arg1.ok_or("Missing segment argument")? {
-        "argument" => "ARG",
-        "local" => "LCL",
-        "this" => "THIS",
-        "that" => "THAT",
-        a @ _ => Err(format!("Invalid segment argument '{}'", a))?,
-    };
-    let v2 = instruction.arg2.ok_or("Missing 2nd argument")?;
-    Ok(match opt {
-        MemOpType::Push => format!(include_str!("./translations/push/segment.asm"), segment, v2),
-        MemOpType::Pop => format!(
-            include_str!("./translations/pop/segment_full.asm"),
-            segment, v2
-        ),
-    })
+/// The format of the report printed on success
+#[derive(Clone, Copy, Default, PartialEq, Eq)]
+enum ReportFormat {
+    #[default]
+    Text,
+    Json,
 }
 
-/// Return the formatted code for a static segment push/pop VM instruction
-fn static_fmt(opt: MemOpType, instruction: &Instruction) -> Result<String, String> {
-    let arg =
-        instruction.file.to_string() + "." + instruction.arg2.ok_or("Missing 2nd argument")?;
-    Ok(match opt {
-        MemOpType::Push => format!(include_str!("./translations/push/direct.asm"), arg),
-        MemOpType::Pop => format!(include_str!("./translations/pop/direct_full.asm"), arg),
-    })
+/// Returns the string as a JSON string literal
+fn json_string(s: &str) -> String {
+    let mut out = String::from('"');
+    for c in s.chars() {
+        match c {
+            '"' => out += "\\\"",
+            '\\' => out += "\\\\",
+            '\n' => out += "\\n",
+            '\r' => out += "\\r",
+            '\t' => out += "\\t",
+            c if (c as u32) < 0x20 => out += &format!("\\u{:04x}", c as u32),
+            c => out.push(c),
+        }
+    }
+    out + "\""
 }
 
-/// Return the formatted code for a temp segment push/pop VM instruction
-fn temp_fmt(opt: MemOpType, instruction: &Instruction) -> Result<String, String> {
-    let arg = "R".to_string()
-        + &(instruction
-            .arg2
-            .ok_or("Missing 2nd argument to temp segment")?
-            .parse::<u8>()
-            .or(Err(format!(
-                "Invalid 2nd argument '{}' to temp segment",
-                instruction.arg2.unwrap()
-            )))?
-            + 5)
-        .to_string();
-    Ok(match opt {
-        MemOpType::Push => format!(include_str!("./translations/push/direct.asm"), arg),
-        MemOpType::Pop => format!(include_str!("./translations/pop/direct_full.asm"), arg),
-    })
+/// Returns the JSON report printed on success with `--format json`
+fn json_report(output: &str, files: &[String], instructions: usize, asm_lines: usize) -> String {
+    format!(
+        "{{\"status\":\"ok\",\"output\":{},\"files\":[{}],\"instructions\":{},\"asm_lines\":{}}}",
+        json_string(output),
+        files
+            .iter()
+            .map(|x| json_string(x))
+            .collect::<Vec<String>>()
+            .join(","),
+        instructions,
+        asm_lines
+    )
 }
 
-/// Return the formatted code for a pointer segment push/pop VM instruction
-fn pointer_fmt(opt: MemOpType, instruction: &Instruction) -> Result<String, String> {
-    let arg = match instruction
-        .arg2
-        .ok_or("Missing 2nd argument to pointer segment")?
-        .parse::<u8>()
-        .or(Err(format!(
-            "Invalid 2nd argument '{}' to pointer segment",
-            instruction.arg2.unwrap()
-        )))? {
-        0 => "THIS",
-        1 => "THAT",
-        a @ _ => Err(format!("Invalid 2nd argument '{}' to pointer segment", a))?,
+/// Returns how many of the errors each file has, as `Foo.vm: no errors, Bar.vm: 2 errors`,
+/// followed by the count of the errors tied to no file, as `global: 1 error`
+fn file_status(files: &[String], errors: &[String]) -> String {
+    let count = |name: &str, n: usize| match n {
+        0 => format!("{}: no errors", name),
+        1 => format!("{}: 1 error", name),
+        n => format!("{}: {} errors", name, n),
     };
-    Ok(match opt {
-        MemOpType::Push => format!(include_str!("./translations/push/direct.asm"), arg),
-        MemOpType::Pop => format!(include_str!("./translations/pop/direct_full.asm"), arg),
-    })
+    let tied = |file: &String, error: &String| error.starts_with(&(file.to_string() + ":"));
+    let mut seen = HashSet::new();
+    let mut status = files
+        .iter()
+        .filter(|file| seen.insert(*file))
+        .map(|file| count(file, errors.iter().filter(|x| tied(file, x)).count()))
+        .collect::<Vec<String>>();
+    // a missing entry function or a file defined twice is no one file's error
+    let global = errors
+        .iter()
+        .filter(|x| !files.iter().any(|file| tied(file, x)))
+        .count();
+    if global > 0 {
+        status.push(count("global", global));
+    }
+    status.join(", ")
 }
 
-/// Returns the Hack assembly representation of the VM "push" and "pop" instruction
-fn generate_memop(instruction: &Instruction) -> Result<String, String> {
-    let opt = match instruction.operation {
-        "push" => MemOpType::Push,
-        "pop" => MemOpType::Pop,
-        _ => Err(format!(
-            "Invalid memmory operation instruction '{}'",
-            instruction.operation
-        ))?,
-    };
-    match instruction {
-        Instruction {
-            arg1: Some(v1),
-            arg2: Some(v2),
-            ..
-        } => {
-            let code = match v1.to_owned() {
-                "constant" if matches!(opt, MemOpType::Push) => {
-                    format!(include_str!("./translations/push/constant.asm"), v2)
+/// The parsed command line arguments
+struct Args {
+    /// Opcode whose translation should be explained instead of translating a program
+    explain: Option<String>,
+    /// Translate the VM lines read from stdin one at a time instead of a program
+    repl: bool,
+    /// Path to the .vm file or directory to translate
+    input_path: String,
+    /// Path to a list of the input paths, one per line, or `-` to read it from stdin
+    files_from: Option<String>,
+    /// Path to the new version of the input to diff the translation of the input against
+    diff: Option<String>,
+    /// File extensions recognized as VM source, without the leading dot
+    extensions: Vec<String>,
+    /// Only translate the VM code found between `<!--vm` and `vm-->` markers
+    extract_markers: bool,
+    /// Read symlinked source files in directory mode instead of skipping them
+    follow_symlinks: bool,
+    /// Translate a single input file whatever its extension
+    force: bool,
+    /// Directory of OS files to translate along with the input
+    os_dir: Option<String>,
+    /// Print warnings about suspicious code before translating
+    lint: bool,
+    /// Print the input in canonical form instead of translating it
+    normalize: bool,
+    /// Print how each instruction was split into tokens instead of translating
+    dump_tokens: bool,
+    /// Print the size of the translation instead of writing it
+    count_only: bool,
+    /// Path to write an HTML view of the translation to instead of the assembly
+    html: Option<String>,
+    /// Directory to also write each file's own translated blocks to, as `<file>.asm`
+    emit_intermediates: Option<String>,
+    /// Directory to write each file's translation to instead of the whole program,
+    /// mirroring the directories of the files listed by `--files-from`
+    output_dir: Option<String>,
+    /// Report the called functions that are defined nowhere instead of translating
+    check: bool,
+    /// The newline style of the written assembly
+    line_ending: LineEnding,
+    /// When to color the error and success messages
+    color: ColorChoice,
+    /// Don't print the success message
+    quiet: bool,
+    /// Print the instruction counts after the success message
+    verbose: bool,
+    /// The format of the report printed on success
+    format: ReportFormat,
+    /// The backend generating the code
+    backend: Box<dyn Backend>,
+    /// The options passed on to the translator
+    options: Options,
+}
+
+/// Parses the command line arguments
+fn parse_args(mut args: impl Iterator<Item = String>) -> Args {
+    let mut input_path = None;
+    let mut diff_mode = false;
+    let mut files_from = None;
+    let mut diff = None;
+    let mut explain = None;
+    let mut repl = false;
+    let mut extensions = vec!["vm".to_string()];
+    let mut extract_markers = false;
+    let mut follow_symlinks = false;
+    let mut force = false;
+    let mut os_dir = None;
+    let mut lint = false;
+    let mut normalize = false;
+    let mut dump_tokens = false;
+    let mut count_only = false;
+    let mut html = None;
+    let mut emit_intermediates = None;
+    let mut output_dir = None;
+    let mut check = false;
+    let mut line_ending = LineEnding::default();
+    let mut color = ColorChoice::default();
+    let mut quiet = false;
+    let mut verbose = false;
+    let mut format = ReportFormat::default();
+    let mut backend: Box<dyn Backend> = Box::new(Hack);
+    let mut options = Options::default();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--explain" => {
+                explain = Some(args.next().expect("Option '--explain' expects an opcode"))
+            }
+            "--repl" => repl = true,
+            "--qualify-labels" => options.qualify_labels = true,
+            "--extract-markers" => extract_markers = true,
+            "--follow-symlinks" => follow_symlinks = true,
+            "--force" => force = true,
+            "--files-from" => {
+                files_from = Some(
+                    args.next()
+                        .expect("Option '--files-from' expects a path or '-'"),
+                )
+            }
+            "--os-dir" => {
+                os_dir = Some(
+                    args.next()
+                        .expect("Option '--os-dir' expects a directory path"),
+                )
+            }
+            "--lint" => lint = true,
+            "--normalize" => normalize = true,
+            "--dump-tokens" => dump_tokens = true,
+            "--count-only" => count_only = true,
+            "--html" => html = Some(args.next().expect("Option '--html' expects a path")),
+            "--emit-intermediates" => {
+                emit_intermediates = Some(
+                    args.next()
+                        .expect("Option '--emit-intermediates' expects a directory"),
+                )
+            }
+            "--output-dir" => {
+                output_dir = Some(args.next().expect("Option '--output-dir' expects a directory"))
+            }
+            "--check" => check = true,
+            "--quiet" | "-q" => quiet = true,
+            "--verbose" | "-v" => verbose = true,
+            "--static-threshold" => {
+                options.static_threshold = args
+                    .next()
+                    .and_then(|x| x.parse().ok())
+                    .expect("Option '--static-threshold' expects a static index")
+            }
+            "--symbols" => {
+                let path = args
+                    .next()
+                    .expect("Option '--symbols' expects the path of a list of function names");
+                let symbols = fs::read_to_string(&path)
+                    .unwrap_or_else(|e| panic!("Unable to read symbol table {}: {}", path, e));
+                options.external_functions.extend(
+                    symbols
+                        .lines()
+                        .map(|x| x.split('#').next().unwrap().trim())
+                        .filter(|x| !x.is_empty())
+                        .map(str::to_string),
+                );
+            }
+            "--max-instructions" => {
+                options.max_instructions = args
+                    .next()
+                    .and_then(|x| x.parse().ok())
+                    .expect("Option '--max-instructions' expects a number of instructions")
+            }
+            "--local-slack" => {
+                options.local_slack = args
+                    .next()
+                    .and_then(|x| x.parse().ok())
+                    .expect("Option '--local-slack' expects a number of locals")
+            }
+            "--ext" => extensions.push(
+                args.next()
+                    .expect("Option '--ext' expects a file extension")
+                    .trim_start_matches('.')
+                    .to_string(),
+            ),
+            "--strip-unused-functions" => options.strip_unused_functions = true,
+            "--include-line-directives" => options.include_line_directives = true,
+            "--trace" => options.trace = true,
+            "--banner" => options.banner = true,
+            "--emit-halt" => options.emit_halt = true,
+            "--sections" => options.sections = true,
+            "--optimize" => options.optimize = true,
+            "--profile" => options.profile = true,
+            "--stable-labels" => options.stable_labels = true,
+            "--resolve-labels" => options.resolve_labels = true,
+            "--minify-labels" => options.minify_labels = true,
+            "--align-comments" => options.align_comments = true,
+            "--trace-templates" => options.trace_templates = true,
+            "--asm-header" => {
+                let path = args
+                    .next()
+                    .expect("Option '--asm-header' expects a file path");
+                options.asm_header = fs::read_to_string(&path)
+                    .unwrap_or_else(|e| panic!("Unable to read assembly header {}: {}", path, e));
+            }
+            "--asm-footer" => {
+                let path = args
+                    .next()
+                    .expect("Option '--asm-footer' expects a file path");
+                options.asm_footer = fs::read_to_string(&path)
+                    .unwrap_or_else(|e| panic!("Unable to read assembly footer {}: {}", path, e));
+            }
+            "--comment-column" => {
+                options.comment_column = args
+                    .next()
+                    .and_then(|x| x.parse().ok())
+                    .expect("Option '--comment-column' expects a column number")
+            }
+            "--annotate-frames" => options.annotate_frames = true,
+            "--compact" => options.compact = true,
+            "--strict-asm" => options.strict_asm = true,
+            "--emit-blocks" => options.emit_blocks = true,
+            "--fail-fast" => options.fail_fast = true,
+            "--validate-output" => options.validate_output = true,
+            "--dialect" => {
+                options.dialect = match args.next().as_deref() {
+                    Some("standard") => Dialect::Standard,
+                    Some("extended") => Dialect::Extended,
+                    _ => panic!("Option '--dialect' expects one of 'standard' or 'extended'"),
                 }
-                "argument" | "local" | "this" | "that" => segment_fmt(opt, instruction)?,
-                "static" => static_fmt(opt, instruction)?,
-                "temp" => temp_fmt(opt, instruction)?,
-                "pointer" => pointer_fmt(opt, instruction)?,
-                o @ _ => Err(format!("Invalid segment argument '{}'", o))?,
-            };
-            Ok(match opt {
-                MemOpType::Push => code + include_str!("./translations/push/main.asm"),
-                MemOpType::Pop => code,
-            })
+            }
+            "--unknown-opcode" => {
+                options.unknown_opcode = match args.next().as_deref() {
+                    Some("error") => UnknownOpcode::Error,
+                    Some("skip") => UnknownOpcode::Skip,
+                    Some("comment") => UnknownOpcode::Comment,
+                    _ => panic!(
+                        "Option '--unknown-opcode' expects one of 'error', 'skip' or 'comment'"
+                    ),
+                }
+            }
+            "--missing-entry" => {
+                options.missing_entry = match args.next().as_deref() {
+                    Some("error") => MissingEntry::Error,
+                    Some("halt") => MissingEntry::Halt,
+                    _ => panic!("Option '--missing-entry' expects one of 'error' or 'halt'"),
+                }
+            }
+            "--assert-address" => {
+                options.assert_address = args
+                    .next()
+                    .and_then(|x| x.parse().ok())
+                    .expect("Option '--assert-address' expects a RAM address")
+            }
+            "--pointer-base" => {
+                options.pointer_base = args
+                    .next()
+                    .and_then(|x| x.parse().ok())
+                    .expect("Option '--pointer-base' expects a RAM address")
+            }
+            "--pop-scratch" => {
+                let register = args
+                    .next()
+                    .expect("Option '--pop-scratch' expects a register");
+                options.pop_scratch = match register.strip_prefix('R').map(|x| x.parse()) {
+                    Some(Ok(n @ 13..=15)) => n,
+                    Some(Ok(n @ 5..=12)) => panic!(
+                        "Option '--pop-scratch' got R{}, which is part of the temp segment (R5-R12)",
+                        n
+                    ),
+                    _ => panic!("Option '--pop-scratch' expects one of 'R13', 'R14' or 'R15'"),
+                }
+            }
+            "--backend" => {
+                backend = match args.next().as_deref() {
+                    Some("hack") => Box::new(Hack),
+                    Some("stub") => Box::new(Stub),
+                    _ => panic!("Option '--backend' expects one of 'hack' or 'stub'"),
+                }
+            }
+            "--opcode-table" => {
+                let path = args
+                    .next()
+                    .expect("Option '--opcode-table' expects a file path");
+                let table = fs::read_to_string(&path)
+                    .unwrap_or_else(|e| panic!("Unable to read opcode table {}: {}", path, e));
+                options.opcode_table = parse_opcode_table(&table)
+                    .unwrap_or_else(|e| panic!("Invalid opcode table {}: {}", path, e));
+            }
+            "--entry" => {
+                options.entry = args
+                    .next()
+                    .expect("Option '--entry' expects a function name")
+            }
+            "--label-prefix" => {
+                options.label_prefix = args
+                    .next()
+                    .filter(|x| {
+                        !x.starts_with(|c: char| c.is_ascii_digit())
+                            && x.chars()
+                                .all(|c| c.is_ascii_alphanumeric() || "_.$:".contains(c))
+                    })
+                    .expect("Option '--label-prefix' expects a prefix of letters, digits, '_', '.', '$' and ':', not starting with a digit")
+            }
+            "--arg" => options.entry_args.push(
+                args.next()
+                    .and_then(|x| x.parse().ok())
+                    .filter(|x| *x < 32768)
+                    .expect("Option '--arg' expects a constant between 0 and 32767"),
+            ),
+            "--line-ending" => {
+                line_ending = match args.next().as_deref() {
+                    Some("lf") => LineEnding::Lf,
+                    Some("crlf") => LineEnding::Crlf,
+                    _ => panic!("Option '--line-ending' expects one of 'lf' or 'crlf'"),
+                }
+            }
+            "--format" => {
+                format = match args.next().as_deref() {
+                    Some("text") => ReportFormat::Text,
+                    Some("json") => ReportFormat::Json,
+                    x => panic!(
+                        "Option '--format' expects one of 'text' or 'json', not {:?}",
+                        x.unwrap_or_default()
+                    ),
+                }
+            }
+            "--color" => {
+                color = match args.next().as_deref() {
+                    Some("auto") => ColorChoice::Auto,
+                    Some("always") => ColorChoice::Always,
+                    Some("never") => ColorChoice::Never,
+                    _ => panic!("Option '--color' expects one of 'auto', 'always' or 'never'"),
+                }
+            }
+            o if o.starts_with("--") => panic!("Unknown option '{}'", o),
+            "diff" if !diff_mode && input_path.is_none() => diff_mode = true,
+            _ if diff_mode && input_path.is_some() => diff = Some(arg),
+            _ => input_path = Some(arg),
         }
-        _ => Err("Memory operation instruction requires two parameters".to_string()),
     }
-}
-
-/// Return the Hack assembly representation of the 2-operand arithmetic & logical VM instructions
-/// (add, sub, or, and)
-fn generate_2op(instruction: &Instruction) -> Result<String, String> {
-    let g = |x| Ok(include_str!("./translations/2op/main.asm").to_string() + x + "\n");
-    match instruction.operation {
-        "add" => g("M=M+D"),
-        "sub" => g("M=M-D"),
-        "or" => g("M=M|D"),
-        "and" => g("M=M&D"),
-        o @ _ => Err(format!(
-            "Invalid 2-operand arithemtic/logical instruction {}",
-            o
-        )),
+    if quiet && verbose {
+        panic!("Options '--quiet' and '--verbose' can't be used together")
     }
-}
-
-/// Return the Hack assembly representation of the 1-operand logical VM instructions
-/// (not, neg)
-fn generate_1op(instruction: &Instruction) -> Result<String, String> {
-    let g = |x| Ok("@SP\nA=M-1\n".to_string() + x + "\n");
-    match instruction.operation {
-        "neg" => g("M=-M"),
-        "not" => g("M=!M"),
-        o @ _ => Err(format!("Invalid 1-operand logical instruction '{}'", o)),
+    if quiet && count_only {
+        panic!("Options '--quiet' and '--count-only' can't be used together")
+    }
+    Args {
+        repl,
+        input_path: match (&explain, &files_from, repl) {
+            (Some(_), _, _) | (_, Some(_), _) | (_, _, true) => input_path.unwrap_or_default(),
+            (None, None, false) => input_path.expect("Path to .vm file or directory not specified"),
+        },
+        diff: match diff_mode {
+            true => Some(diff.expect("Subcommand 'diff' expects an old and a new path")),
+            false => None,
+        },
+        explain,
+        files_from,
+        extensions,
+        extract_markers,
+        follow_symlinks,
+        force,
+        os_dir,
+        lint,
+        normalize,
+        dump_tokens,
+        count_only,
+        html,
+        emit_intermediates,
+        output_dir,
+        check,
+        line_ending,
+        color,
+        quiet,
+        verbose,
+        format,
+        backend,
+        options,
     }
 }
 
-/// Return the Hack assembly representation of the logical comparison VM instructions
-/// (eq, gt, lt)
-fn generate_cmp(instruction: &Instruction) -> Result<String, String> {
-    let g = |x| {
-        Ok(format!(
-            include_str!("./translations/cmp/main.asm"),
-            instruction.id, x, instruction.id, instruction.id, instruction.id, instruction.id
-        ))
+/// Returns the path the translation of the input is written to by default: the name of
+/// the input file or directory with its extension replaced by `.asm`, next to the file
+/// or inside the directory. An input named `.` or `..` is named after the directory it
+/// stands for. Fails on the root directory, which has no name, or a path that isn't
+/// valid UTF-8
+fn output_path(p: &Path) -> Result<String, String> {
+    let canonical = p.canonicalize().ok();
+    let name = p
+        .file_name()
+        .or_else(|| canonical.as_deref()?.file_name())
+        .map(Path::new)
+        .ok_or_else(|| format!("Unable to name the output of {}", p.display()))?
+        .with_extension("asm");
+    let path = match p.is_file() {
+        true => p.with_file_name(name),
+        false => p.join(name),
     };
-    match instruction.operation {
-        "eq" => g("JEQ"),
-        "gt" => g("JGT"),
-        "lt" => g("JLT"),
-        o @ _ => Err(format!("Invalid logical comparison instruction '{}'", o)),
-    }
+    path.to_str()
+        .map(str::to_string)
+        .ok_or_else(|| format!("Output path {} is not valid UTF-8", path.display()))
 }
 
-/// Returns the Hack assembly representation of the branching VM instructions
-/// (label, goto, if-goto)
-fn generate_branching(instruction: &Instruction) -> Result<String, String> {
-    let l_name = instruction.file.to_string()
-        + "."
-        + instruction.frame.unwrap_or("global")
-        + "$"
-        + instruction.arg1.ok_or("Missing label name argument")?;
-    Ok(match instruction.operation {
-        "label" => format!("({})\n", l_name),
-        "goto" => format!("@{}\n0;JMP\n", l_name),
-        "if-goto" => format!(include_str!("./translations/branching/if-goto.asm"), l_name),
-        o @ _ => Err(format!("Invalid branching instruction '{}'", o))?,
-    })
+/// Returns the path of the directory with only its named parts, so that it stays
+/// inside the directory it is joined to: `../src/./a` becomes `src/a`
+fn mirrored(dir: &Path) -> PathBuf {
+    dir.components()
+        .filter_map(|x| match x {
+            Component::Normal(x) => Some(x),
+            _ => None,
+        })
+        .collect()
 }
 
-/// Returns the Hack assembly representation of the functions VM instructions
-/// (function, call, return)
-fn generate_functions(instruction: &Instruction) -> Result<String, String> {
-    Ok(match instruction.operation {
-        "function" => {
-            let arg1 = instruction.arg1.ok_or("Missing function name argument")?;
-            let arg2 = instruction
-                .arg2
-                .ok_or("Missing n_vars argument for function")?;
-            let n_vars = arg2.parse::<usize>().or(Err(format!(
-                "Invalid n_vars argument for function, '{}'",
-                arg2
-            )))?;
-            format!(
-                include_str!("./translations/functions/function.asm"),
-                arg1,
-                n_vars,
-                "M=0\nA=A+1\n".repeat(n_vars)
-            )
-        }
-        "call" => {
-            let arg1 = instruction
-                .arg1
-                .ok_or("Missing function name argument to call")?;
-            let arg2 = instruction
-                .arg2
-                .ok_or("Missing n_args argument for function call")?;
-            let n_args = arg2.parse::<usize>().or(Err(format!(
-                "Invalid n_args argument for function call, '{}",
-                arg2
-            )))?;
+/// Writes each file's translation to `<dir>/<source dir>/<file>.asm`, the source dir
+/// being the file's directory mirrored from the input if known,
+/// or reports why it can't and exits
+fn write_per_file(
+    dir: &Path,
+    files: Vec<(String, String)>,
+    source_dirs: &HashMap<String, PathBuf>,
+    line_ending: LineEnding,
+) {
+    for (name, code) in files {
+        let parent = match source_dirs.get(&name) {
+            Some(source_dir) => dir.join(source_dir),
+            None => dir.to_path_buf(),
+        };
+        fs::create_dir_all(&parent).unwrap_or_else(|e| {
+            eprintln!("Unable to create {}: {}", parent.display(), e);
+            std::process::exit(1)
+        });
+        let path = parent.join(name + ".asm");
+        fs::write(&path, line_ending.apply(code)).unwrap_or_else(|e| {
+            eprintln!("Unable to write {}: {}", path.display(), e);
+            std::process::exit(1)
+        });
+    }
+}
 
-            let return_label =
-                instruction.frame.unwrap_or("global").to_string()
-                + "$ret."
-                + &instruction.id.to_string();
-            
-            format!(include_str!("./translations/functions/call.asm"), return_label, n_args + 5, arg1, return_label)
+/// Returns the stem of the file, naming its statics, or reports that it has none
+/// usable as a name and exits
+fn file_stem(path: &Path) -> String {
+    match path.file_stem().and_then(|x| x.to_str()) {
+        Some(stem) => stem.to_string(),
+        None => {
+            eprintln!("Input file {} has no valid UTF-8 name", path.display());
+            std::process::exit(1)
         }
-        "return" => include_str!("./translations/functions/return.asm").to_string(),
-        o @ _ => Err(format!("Invalid functions instruction '{}'", o))?,
+    }
+}
+
+/// Returns the contents of the file, or reports why it can't be read and exits
+fn read_file(path: &Path) -> String {
+    fs::read_to_string(path).unwrap_or_else(|e| {
+        eprintln!("Unable to read {}: {}", path.display(), e);
+        std::process::exit(1)
     })
 }
 
-/// Returns the Hack assembly representation of the VM instruction
-fn generate_code(instruction: &Instruction) -> Result<String, String> {
-    let err_fmt = |x| format!("#{} '{}': {}", instruction.id, instruction.raw, x);
-    let g =
-        |f: fn(&Instruction) -> Result<String, String>| {
-            Ok("// ".to_string()
-                + instruction.raw
-                + "\n"
-                + f(instruction).map_err(err_fmt)?.trim_end())
-        };
-    match instruction.operation {
-        "push" | "pop" => g(generate_memop),
-        "add" | "sub" | "and" | "or" => g(generate_2op),
-        "neg" | "not" => g(generate_1op),
-        "eq" | "gt" | "lt" => g(generate_cmp),
-        "label" | "goto" | "if-goto" => g(generate_branching),
-        "function" | "call" | "return" => g(generate_functions),
-        o @ _ => Err(err_fmt(format!("Invalid VM instruction '{}'", o))),
+/// Reads the VM files to translate from the input path, a single file or a directory,
+/// keeping only the code between markers if requested
+fn read_input(
+    p: &Path,
+    extensions: &[String],
+    extract: bool,
+    follow_symlinks: bool,
+    force: bool,
+) -> Vec<(String, String)> {
+    let is_source = |p: &Path| {
+        let extension = p.extension().unwrap_or_default();
+        extensions.iter().any(|e| extension == e.as_str())
+    };
+    let contents: Vec<(String, String)> = {
+        if p.is_file() {
+            if !(force || extract || is_source(p)) {
+                eprintln!(
+                    "Input file {} has to be a .{} file or a directory, use --force to translate it anyway",
+                    p.display(),
+                    extensions.join(", .")
+                );
+                std::process::exit(1)
+            }
+            vec![(file_stem(p), read_file(p))]
+        } else if p.is_dir() {
+            // directory mode doesn't recurse, so no traversal can cycle: the canonical paths
+            // seen only keep a file reached through several symlinks from being read twice,
+            // while a symlink loop fails to canonicalize and is skipped
+            let mut visited = HashSet::new();
+            // the order entries are listed in depends on the filesystem,
+            // sorting keeps the output the same everywhere
+            let mut paths = fs::read_dir(p)
+                .and_then(|entries| {
+                    entries
+                        .map(|e| Ok(e?.path()))
+                        .collect::<io::Result<Vec<_>>>()
+                })
+                .unwrap_or_else(|e| {
+                    eprintln!("Unable to read {}: {}", p.display(), e);
+                    std::process::exit(1)
+                });
+            paths.sort();
+            paths
+                .into_iter()
+                .filter(|p| is_source(p))
+                .filter(|p| follow_symlinks || !p.is_symlink())
+                .filter(|p| match p.canonicalize() {
+                    Ok(c) => visited.insert(c),
+                    Err(e) => {
+                        eprintln!("Skipping {}: {}", p.display(), e);
+                        false
+                    }
+                })
+                // a directory named like a source file is not one
+                .filter(|p| p.is_file())
+                .map(|p| (file_stem(&p), read_file(&p)))
+                .collect()
+        } else {
+            panic!("Input path is neither a file nor a directory")
+        }
+    };
+    match extract {
+        true => contents
+            .into_iter()
+            .map(|(n, c)| (n, extract_markers(&c).unwrap_or_else(|e| panic!("{}", e))))
+            .collect(),
+        false => contents,
     }
 }
 
-/// Given a vector of tuples of a VM filename and its contents,
-/// return the translated Hack assembly code
-fn translate(contents: Vec<(String, String)>) -> Result<String, Vec<String>> {
-    let instructions = contents
-        .iter()
-        .map(|(file, c)| {
-            parse_contents(c)
+fn main() {
+    let Args {
+        explain: opcode,
+        repl,
+        input_path,
+        files_from,
+        diff: new_path,
+        extensions,
+        extract_markers: extract,
+        follow_symlinks,
+        force,
+        os_dir,
+        lint: run_lint,
+        normalize: run_normalize,
+        dump_tokens,
+        count_only,
+        html,
+        emit_intermediates,
+        output_dir,
+        check,
+        line_ending,
+        color,
+        quiet,
+        verbose,
+        format,
+        backend,
+        options,
+    } = parse_args(env::args().skip(1));
+    #[cfg(feature = "log")]
+    if verbose {
+        vmtranslator::set_logger(|level, message| eprintln!("{}: {}", level, message));
+    }
+    if let Some(opcode) = opcode {
+        match explain(&opcode) {
+            Ok(v) => print!("{}", v),
+            Err(e) => {
+                eprintln!("{}", e);
+                std::process::exit(1)
+            }
+        }
+        return;
+    }
+    if repl {
+        let interactive = io::stdin().is_terminal();
+        let enabled = color.enabled(io::stderr().is_terminal());
+        let mut ctx = TranslateContext::new("Repl", options);
+        let mut lines = io::stdin().lines();
+        loop {
+            if interactive {
+                print!("> ");
+                io::stdout().flush().unwrap();
+            }
+            let Some(line) = lines.next() else {
+                break;
+            };
+            match translate_line(&line.unwrap(), &mut ctx) {
+                Ok(v) => print!("{}", v),
+                Err(e) => eprintln!("{}", paint(&e.to_string(), "31", enabled)),
+            }
+        }
+        if interactive {
+            println!();
+        }
+        return;
+    }
+    let p = Path::new(files_from.as_deref().unwrap_or(&input_path));
+    if let Some(new_path) = new_path {
+        let old = read_input(p, &extensions, extract, follow_symlinks, force);
+        let mut new = read_input(
+            Path::new(&new_path),
+            &extensions,
+            extract,
+            follow_symlinks,
+            force,
+        );
+        // Two versions of a single file are compared as the same file
+        if let ([(old_name, _)], [(new_name, _)]) = (old.as_slice(), new.as_mut_slice()) {
+            new_name.clone_from(old_name);
+        }
+        match diff(old, new, &options) {
+            Ok(v) => {
+                for difference in v {
+                    println!("{}\n", difference);
+                }
+            }
+            Err(v) => {
+                let enabled = color.enabled(io::stderr().is_terminal());
+                eprintln!("{}", paint(&v.join("\n"), "31", enabled));
+            }
+        }
+        return;
+    }
+    // the directory of each listed file, mirrored under the output directory
+    let mut source_dirs = HashMap::new();
+    let mut contents = match files_from {
+        Some(_) => {
+            let list = match p.to_str() {
+                Some("-") => io::read_to_string(io::stdin()).unwrap(),
+                _ => read_file(p),
+            };
+            let mut contents = vec![];
+            for x in list
+                .lines()
+                .map(|x| x.trim())
+                .filter(|x| !(x.is_empty() || x.starts_with('#')))
+            {
+                let path = Path::new(x);
+                let files = read_input(path, &extensions, extract, follow_symlinks, force);
+                let dir = match path.is_dir() {
+                    true => path,
+                    false => path.parent().unwrap_or(Path::new("")),
+                };
+                source_dirs.extend(files.iter().map(|(n, _)| (n.clone(), mirrored(dir))));
+                contents.extend(files);
+            }
+            contents
+        }
+        None => read_input(p, &extensions, extract, follow_symlinks, force),
+    };
+    if let Some(os_dir) = os_dir {
+        let os_path = Path::new(&os_dir);
+        assert!(os_path.is_dir(), "OS path {} is not a directory", os_dir);
+        let mut os = read_input(os_path, &extensions, false, follow_symlinks, false);
+        os.sort();
+        // the program's own files take precedence over OS files of the same name
+        os.retain(|(n, _)| !contents.iter().any(|(m, _)| m == n));
+        contents.extend(os);
+    }
+    if run_normalize {
+        for (name, c) in &contents {
+            if contents.len() > 1 {
+                println!("// {}.vm", name);
+            }
+            print!("{}", normalize(c));
+        }
+        return;
+    }
+    if dump_tokens {
+        let quote = |x: Option<&str>| x.map_or("None".to_string(), |x| format!("'{}'", x));
+        for (name, c) in &contents {
+            if contents.len() > 1 {
+                println!("// {}.vm", name);
+            }
+            for x in parse(name, c).filter_map(Result::ok) {
+                let extra = x
+                    .extra
+                    .iter()
+                    .map(|x| format!("'{}'", x))
+                    .collect::<Vec<String>>();
+                println!(
+                    "line {}: op='{}' arg1={} arg2={} extra=[{}]",
+                    x.line,
+                    x.operation,
+                    quote(x.arg1),
+                    quote(x.arg2),
+                    extra.join(", ")
+                );
+            }
+        }
+        return;
+    }
+    if run_lint {
+        let enabled = color.enabled(io::stderr().is_terminal());
+        for warning in lint(&contents, &options) {
+            eprintln!("{}", paint(&warning, "33", enabled));
+        }
+    }
+    if check {
+        let instructions = contents
+            .iter()
+            .flat_map(|(name, c)| parse(name, c).filter_map(Result::ok))
+            .collect::<Vec<Instruction>>();
+        let unresolved = unresolved_calls(&instructions, &options.external_functions);
+        for (callee, callers) in &unresolved {
+            let callers = callers
                 .iter()
-                .enumerate()
-                .map(|(i, x)| Instruction::new(x, i, file).unwrap())
-                .collect::<Vec<Instruction>>()
-        })
-        .flatten()
-        .collect::<Vec<Instruction>>();
-    let instructions_clone = instructions.clone();
-    let res = instructions
-        .into_iter()
-        .map(|mut x| {
-            x.set_frame(&instructions_clone);
-            x
-        })
-        .map(|x| generate_code(&x))
-        .fold((vec![], vec![]), |(mut o, mut e), item| match item {
+                .map(|x| x.to_string() + ".vm")
+                .collect::<Vec<String>>();
+            println!(
+                "undefined reference to '{}' from {}",
+                callee,
+                callers.join(", ")
+            );
+        }
+        if !unresolved.is_empty() {
+            std::process::exit(1);
+        }
+        return;
+    }
+    if let Some(html_path) = html {
+        match translate_html(backend.as_ref(), contents, &options) {
             Ok(v) => {
-                o.push(v);
-                (o, e)
+                fs::write(&html_path, v).unwrap_or_else(|e| {
+                    eprintln!("Unable to write {}: {}", html_path, e);
+                    std::process::exit(1)
+                });
+                if !quiet {
+                    let message = format!(
+                        "Successfully translated {} into {}",
+                        p.file_name().unwrap_or(p.as_os_str()).to_string_lossy(),
+                        html_path
+                    );
+                    let enabled = color.enabled(io::stdout().is_terminal());
+                    println!("{}", paint(&message, "32", enabled));
+                }
             }
             Err(v) => {
-                e.push(v);
-                (o, e)
+                let enabled = color.enabled(io::stderr().is_terminal());
+                eprintln!("{}", paint(&v.join("\n"), "31", enabled));
             }
+        }
+        return;
+    }
+    let vm_instructions = contents
+        .iter()
+        .map(|(name, c)| parse(name, c).count())
+        .sum::<usize>();
+    let names = contents
+        .iter()
+        .map(|(name, _)| name.to_string() + ".vm")
+        .collect::<Vec<String>>();
+    let intermediates = emit_intermediates.map(|dir| (dir, contents.clone()));
+    let per_file = output_dir.map(|dir| (dir, contents.clone()));
+    let result = translate_with(backend.as_ref(), contents, &options);
+    if let (Ok(_), Some((dir, contents))) = (&result, intermediates) {
+        let files = translate_per_file(backend.as_ref(), contents, &options).unwrap_or_else(|e| {
+            eprintln!("{}", e.join("\n"));
+            std::process::exit(1)
         });
-    match res.1.len() {
-        0 => Ok(include_str!("./translations/init.asm").to_string()
-            + &res
-                .0
-                .iter()
-                .fold(String::new(), |acc, item| acc + &item + "\n\n")),
-        _ => Err(res.1),
+        write_per_file(Path::new(&dir), files, &HashMap::new(), line_ending);
     }
-}
-
-fn main() {
-    let input_path = env::args()
-        .nth(1)
-        .expect("Path to .vm file or directory not specified");
-    let p = Path::new(&input_path);
-    let contents = {
-        if p.is_file() {
-            assert!(
-                p.extension().unwrap() == "vm",
-                "Input file has to be a .vm file or a directory"
+    if let (Ok(_), Some((dir, contents))) = (&result, per_file) {
+        let files = translate_per_file(backend.as_ref(), contents, &options).unwrap_or_else(|e| {
+            eprintln!("{}", e.join("\n"));
+            std::process::exit(1)
+        });
+        write_per_file(Path::new(&dir), files, &source_dirs, line_ending);
+        if !quiet {
+            let message = format!(
+                "Successfully translated {} into {}",
+                p.file_name().unwrap_or(p.as_os_str()).to_string_lossy(),
+                dir
             );
-            vec![(
-                p.file_stem().unwrap().to_str().unwrap().to_owned(),
-                fs::read_to_string(p).unwrap(),
-            )]
-        } else if p.is_dir() {
-            fs::read_dir(&p)
-                .unwrap()
-                .map(|e| e.unwrap())
-                .map(|e| e.path())
-                .filter(|p| p.extension().unwrap_or_default() == "vm")
-                .map(|p| (p.file_stem().unwrap().to_str().unwrap().to_owned(), p))
-                .map(|(n, p)| (n, fs::read_to_string(p).unwrap()))
-                .collect()
-        } else {
-            panic!("Input path is neither a file nor a directory")
+            let enabled = color.enabled(io::stdout().is_terminal());
+            println!("{}", paint(&message, "32", enabled));
         }
-    };
-    match translate(contents) {
-        Ok(v) => {
-            let output_path = if p.is_file() {
-                input_path.replace(".vm", ".asm")
-            } else {
-                let mut path_buff = p.to_owned().to_path_buf();
-                path_buff.push(p.file_name().unwrap().to_str().unwrap().to_owned() + ".asm");
-                path_buff.to_str().unwrap().to_owned()
-            };
-            fs::write(&output_path, v).unwrap();
+        return;
+    }
+    match result {
+        Ok(v) if count_only => {
+            println!("VM instructions: {}", vm_instructions);
+            println!("Assembly instructions: {}", count_instructions(&v));
             println!(
+                "Labels: {}",
+                v.lines().filter(|x| x.trim().starts_with('(')).count()
+            );
+        }
+        Ok(_) if p.to_str() == Some("-") && format == ReportFormat::Json => {
+            panic!("Option '--format json' can't be used when writing the output to stdout")
+        }
+        Ok(v) if p.to_str() == Some("-") => print!("{}", line_ending.apply(v)),
+        Ok(v) => {
+            let output_path = output_path(p).unwrap_or_else(|e| {
+                eprintln!("{}", e);
+                std::process::exit(1)
+            });
+            let asm_lines = v.lines().count();
+            let v = line_ending.apply(v);
+            fs::write(&output_path, v).unwrap_or_else(|e| {
+                eprintln!("Unable to write {}: {}", output_path, e);
+                std::process::exit(1)
+            });
+            if format == ReportFormat::Json {
+                println!(
+                    "{}",
+                    json_report(&output_path, &names, vm_instructions, asm_lines)
+                );
+                return;
+            }
+            if quiet {
+                return;
+            }
+            let message = format!(
                 "Successfully translated {} into {}",
-                p.file_name().unwrap().to_str().unwrap(), output_path
+                p.file_name().unwrap_or(p.as_os_str()).to_string_lossy(),
+                output_path
             );
+            let enabled = color.enabled(io::stdout().is_terminal());
+            println!("{}", paint(&message, "32", enabled));
+            if verbose {
+                println!(
+                    "VM instructions: {}, assembly lines: {}",
+                    vm_instructions, asm_lines
+                );
+            }
         }
         Err(v) => {
-            eprintln!(
-                "{}",
-                v.into_iter()
-                    .reduce(|acc, item| acc + "\n" + &item)
-                    .unwrap()
-            );
+            let summary = (names.len() > 1).then(|| file_status(&names, &v));
+            let message = v
+                .into_iter()
+                .reduce(|acc, item| acc + "\n" + &item)
+                .unwrap();
+            let enabled = color.enabled(io::stderr().is_terminal());
+            eprintln!("{}", paint(&message, "31", enabled));
+            if let Some(summary) = summary {
+                eprintln!("{}", summary);
+            }
         }
     };
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    /// Returns a new empty directory under the system's temporary directory
+    fn scratch_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("vmtranslator-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn output_path_replaces_the_extension_of_files_and_directories() {
+        let dir = scratch_dir("output");
+        fs::create_dir(dir.join("Prog.v2")).unwrap();
+        fs::write(dir.join("Main.vm"), "").unwrap();
+        fs::write(dir.join("files.txt"), "").unwrap();
+        let output = |name: &str| output_path(&dir.join(name)).map(PathBuf::from);
+        assert_eq!(output("Main.vm"), Ok(dir.join("Main.asm")));
+        assert_eq!(output("files.txt"), Ok(dir.join("files.asm")));
+        assert_eq!(output("Prog.v2"), Ok(dir.join("Prog.v2").join("Prog.asm")));
+        let current = env::current_dir().unwrap();
+        let name = current.file_name().unwrap().to_str().unwrap();
+        assert_eq!(output_path(Path::new(".")), Ok(format!("./{}.asm", name)));
+        assert_eq!(
+            output_path(Path::new("/")),
+            Err("Unable to name the output of /".to_string())
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn directory_input_skips_directories_named_like_sources() {
+        let dir = scratch_dir("dirs");
+        fs::create_dir(dir.join("Nested.vm")).unwrap();
+        fs::write(dir.join("Sys.vm"), "function Sys.init 0\n").unwrap();
+        let contents = read_input(&dir, &["vm".to_string()], false, false, false);
+        assert_eq!(
+            contents,
+            [("Sys".to_string(), "function Sys.init 0\n".to_string())]
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn added_extensions_are_read_from_directories() {
+        let dir = scratch_dir("ext");
+        fs::write(dir.join("Main.hvm"), "function Main.main 0\n").unwrap();
+        fs::write(dir.join("Sys.vm"), "function Sys.init 0\n").unwrap();
+        let names = |extensions: &[&str]| {
+            let extensions: Vec<String> = extensions.iter().map(|x| x.to_string()).collect();
+            let mut contents = read_input(&dir, &extensions, false, false, false);
+            contents.sort();
+            contents.into_iter().map(|(n, _)| n).collect::<Vec<_>>()
+        };
+        assert_eq!(names(&["vm"]), ["Sys"]);
+        assert_eq!(names(&["vm", "hvm"]), ["Main", "Sys"]);
+        assert_eq!(args(&["Prog", "--ext", "hvm"]).extensions, ["vm", "hvm"]);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn mirrored_keeps_only_the_named_parts() {
+        assert_eq!(mirrored(Path::new("src/a")), Path::new("src/a"));
+        assert_eq!(mirrored(Path::new("../src/./a")), Path::new("src/a"));
+        assert_eq!(mirrored(Path::new("/abs/src")), Path::new("abs/src"));
+        assert_eq!(mirrored(Path::new("")), Path::new(""));
+    }
+
+    /// Parses the arguments given after the program name
+    fn args(args: &[&str]) -> Args {
+        parse_args(args.iter().map(|x| x.to_string()))
+    }
+
+    #[test]
+    #[should_panic(expected = "Options '--quiet' and '--verbose' can't be used together")]
+    fn quiet_rejects_verbose() {
+        args(&["-q", "Main.vm", "--verbose"]);
+    }
+
+    #[test]
+    #[should_panic(expected = "Options '--quiet' and '--count-only' can't be used together")]
+    fn quiet_rejects_count_only() {
+        args(&["--count-only", "--quiet", "Main.vm"]);
+    }
+
+    #[test]
+    #[should_panic(
+        expected = "Option '--pop-scratch' got R7, which is part of the temp segment (R5-R12)"
+    )]
+    fn pop_scratch_rejects_temp_registers() {
+        args(&["Main.vm", "--pop-scratch", "R7"]);
+    }
+
+    #[test]
+    fn json_report_escapes_the_paths() {
+        let files = ["Main.vm".to_string(), "Say \"hi\".vm".to_string()];
+        assert_eq!(
+            json_report("C:\\out\\Prog.asm", &files, 12, 240),
+            r#"{"status":"ok","output":"C:\\out\\Prog.asm","files":["Main.vm","Say \"hi\".vm"],"instructions":12,"asm_lines":240}"#
+        );
+    }
+
+    #[test]
+    fn file_status_counts_errors_tied_to_no_file() {
+        let files = ["Main.vm".to_string(), "Sys.vm".to_string()];
+        let errors = [
+            "Main.vm:3:1 #2 'pop constant 1': cannot pop to constant".to_string(),
+            "bootstrap requires a Sys.init function, none found".to_string(),
+        ];
+        assert_eq!(
+            file_status(&files, &errors),
+            "Main.vm: 1 error, Sys.vm: no errors, global: 1 error"
+        );
+        assert_eq!(
+            file_status(&files, &errors[1..]),
+            "Main.vm: no errors, Sys.vm: no errors, global: 1 error"
+        );
+    }
+}
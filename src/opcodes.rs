@@ -0,0 +1,172 @@
+use crate::Instruction;
+use std::collections::HashMap;
+
+/// Unescapes the contents of a basic TOML string
+fn unescape(s: &str) -> Result<String, String> {
+    let mut out = String::new();
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        out.push(match chars.next() {
+            Some('n') => '\n',
+            Some('t') => '\t',
+            Some('"') => '"',
+            Some('\\') => '\\',
+            Some(o) => Err(format!("unknown escape sequence '\\{}'", o))?,
+            None => Err("unterminated escape sequence")?,
+        });
+    }
+    Ok(out)
+}
+
+/// Parses an opcode table, a TOML file of `opcode = "template"` entries whose
+/// templates may also be multi-line `"""` strings. Only this subset of TOML is accepted
+pub fn parse_opcode_table(contents: &str) -> Result<HashMap<String, String>, String> {
+    let mut table = HashMap::new();
+    let mut lines = contents.lines().enumerate();
+    while let Some((i, line)) = lines.next() {
+        let err = |x: &str| format!("line {}: {}", i + 1, x);
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let (key, value) = line
+            .split_once('=')
+            .ok_or_else(|| err("expected 'opcode = \"template\"'"))?;
+        let key = key.trim().trim_matches('"');
+        if key.is_empty() || key.contains(char::is_whitespace) {
+            Err(err(&format!("invalid opcode name '{}'", key)))?;
+        }
+        let value = value.trim();
+        let template = if let Some(rest) = value.strip_prefix("\"\"\"") {
+            // A newline right after the opening quotes is not part of the string
+            let mut raw = rest.to_string();
+            while !raw.contains("\"\"\"") {
+                let (_, next) = lines
+                    .next()
+                    .ok_or_else(|| err("unterminated multi-line string"))?;
+                raw = raw + "\n" + next;
+            }
+            let raw = raw.strip_prefix('\n').unwrap_or(&raw);
+            let (raw, trailing) = raw.split_once("\"\"\"").unwrap();
+            if !trailing.trim().is_empty() {
+                Err(err("unexpected characters after the template"))?;
+            }
+            unescape(raw).map_err(|e| err(&e))?
+        } else if let Some(rest) = value.strip_prefix('"') {
+            let raw = rest
+                .strip_suffix('"')
+                .ok_or_else(|| err("unterminated string"))?;
+            unescape(raw).map_err(|e| err(&e))?
+        } else {
+            Err(err("expected a string template"))?
+        };
+        if table.insert(key.to_string(), template).is_some() {
+            Err(err(&format!("opcode '{}' is defined twice", key)))?;
+        }
+    }
+    Ok(table)
+}
+
+/// Fills in a custom opcode's template for the instruction:
+/// `{0}`, `{1}`, ... with its arguments and `{id}` with its unique id
+pub(crate) fn expand_template(template: &str, instruction: &Instruction) -> Result<String, String> {
+    let args = [instruction.arg1, instruction.arg2]
+        .into_iter()
+        .flatten()
+        .chain(instruction.extra.iter().copied())
+        .collect::<Vec<&str>>();
+    let mut out = String::new();
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        out += &rest[..start];
+        let end = rest[start..]
+            .find('}')
+            .ok_or("unterminated placeholder in custom opcode template")?;
+        let placeholder = &rest[start + 1..start + end];
+        out += &match placeholder {
+            "id" => instruction.label_id.to_string(),
+            n => {
+                let n = n.parse::<usize>().or(Err(format!(
+                    "invalid placeholder '{{{}}}' in custom opcode template",
+                    n
+                )))?;
+                args.get(n)
+                    .ok_or(format!(
+                        "custom opcode '{}' uses argument {{{}}}, got {} argument{}",
+                        instruction.operation,
+                        n,
+                        args.len(),
+                        if args.len() == 1 { "" } else { "s" }
+                    ))?
+                    .to_string()
+            }
+        };
+        rest = &rest[start + end + 1..];
+    }
+    Ok(out + rest)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_single_and_multi_line_templates() {
+        let table = parse_opcode_table(
+            "# comment\n\"inc\" = \"@SP\\nA=M-1\\nM=M+1\"\n\ndup = \"\"\"\n@SP\nA=M-1\nD=M\n\"\"\"\n",
+        )
+        .unwrap();
+        assert_eq!(table["inc"], "@SP\nA=M-1\nM=M+1");
+        assert_eq!(table["dup"], "@SP\nA=M-1\nD=M\n");
+    }
+
+    #[test]
+    fn reports_the_line_of_malformed_entries() {
+        let error = |contents: &str| parse_opcode_table(contents).unwrap_err();
+        assert_eq!(error("inc\n"), "line 1: expected 'opcode = \"template\"'");
+        assert_eq!(
+            error("\nmy op = \"\""),
+            "line 2: invalid opcode name 'my op'"
+        );
+        assert_eq!(error("inc = \"@SP"), "line 1: unterminated string");
+        assert_eq!(error("inc = @SP"), "line 1: expected a string template");
+        assert_eq!(
+            error("inc = \"\\q\""),
+            "line 1: unknown escape sequence '\\q'"
+        );
+        assert_eq!(
+            error("inc = \"\"\"\n@SP\n"),
+            "line 1: unterminated multi-line string"
+        );
+        assert_eq!(
+            error("inc = \"\"\n\"inc\" = \"\""),
+            "line 2: opcode 'inc' is defined twice"
+        );
+    }
+
+    #[test]
+    fn expands_the_arguments_and_id() {
+        let instruction = Instruction::new("swap 3 4", 7, 1, "Main").unwrap();
+        let expand = |template: &str| expand_template(template, &instruction);
+        assert_eq!(
+            expand("@{1}\n@{0}\n(L{id})"),
+            Ok("@4\n@3\n(L7)".to_string())
+        );
+        assert_eq!(
+            expand("@{2}"),
+            Err("custom opcode 'swap' uses argument {2}, got 2 arguments".to_string())
+        );
+        assert_eq!(
+            expand("@{x}"),
+            Err("invalid placeholder '{x}' in custom opcode template".to_string())
+        );
+        assert_eq!(
+            expand("@{0"),
+            Err("unterminated placeholder in custom opcode template".to_string())
+        );
+    }
+}
@@ -0,0 +1,190 @@
+/// A push followed by an instruction popping the value right back:
+/// the value is still in D, so only A needs to be pointed at the top of the stack
+const PUSH_POP: [&str; 8] = ["@SP", "M=M+1", "A=M-1", "M=D", "@SP", "M=M-1", "A=M", "D=M"];
+const PUSH_POP_REPLACEMENT: [&str; 2] = ["@SP", "A=M"];
+
+/// Number of instructions from the jump of a comparison
+/// to the jump of the if-goto branching on its result
+const COMPARE_BRANCH_LEN: usize = 18;
+
+/// Returns whether the line is a comment or empty,
+/// and so can't affect the behaviour of the assembly
+fn is_inert(line: &str) -> bool {
+    line.is_empty() || line.starts_with("//")
+}
+
+/// Returns the next n instructions starting at line i, skipping inert lines,
+/// paired with their offset from i
+fn window<'a>(lines: &[&'a str], i: usize, n: usize) -> Vec<(usize, &'a str)> {
+    lines[i..]
+        .iter()
+        .copied()
+        .enumerate()
+        .filter(|(_, x)| !is_inert(x))
+        .take(n)
+        .collect()
+}
+
+/// Replaces the instructions of the window starting at line i with the replacement,
+/// keeping the comments in between, and returns the line following the replacement
+fn replace<'a>(
+    lines: &mut Vec<&'a str>,
+    i: usize,
+    window: &[(usize, &str)],
+    replacement: &[&'a str],
+) -> usize {
+    let end = i + window.last().unwrap().0;
+    let comments = lines[i..=end]
+        .iter()
+        .copied()
+        .filter(|x| is_inert(x))
+        .collect::<Vec<&str>>();
+    let kept = comments.len() + replacement.len();
+    let replacement = comments
+        .into_iter()
+        .chain(replacement.iter().copied())
+        .collect::<Vec<&str>>();
+    lines.splice(i..=end, replacement);
+    i + kept
+}
+
+/// Removes the redundant memory round-trips of values
+/// stored to the stack and immediately loaded back
+fn elide_push_pop(lines: &mut Vec<&str>) {
+    let mut i = 0;
+    while i < lines.len() {
+        let window = window(lines, i, PUSH_POP.len());
+        let matches = window.len() == PUSH_POP.len()
+            && window.iter().zip(PUSH_POP).all(|((_, x), y)| *x == y);
+        if !matches || is_inert(lines[i]) {
+            i += 1;
+            continue;
+        }
+        i = replace(lines, i, &window, &PUSH_POP_REPLACEMENT);
+    }
+}
+
+/// Returns the comparison's jump and the if-goto's target if the window is
+/// the end of a comparison followed by an if-goto branching on its result
+fn compare_branch<'a>(window: &[(usize, &'a str)]) -> Option<(&'a str, &'a str)> {
+    if window.len() != COMPARE_BRANCH_LEN {
+        return None;
+    }
+    let id = window[0].1.strip_prefix('@')?.strip_suffix(".true")?;
+    let condition = window[1].1;
+    let target = window[COMPARE_BRANCH_LEN - 2].1;
+    let expected = [
+        format!("({}.false)", id),
+        "D=0".to_string(),
+        format!("@{}.cont", id),
+        "0;JMP".to_string(),
+        format!("({}.true)", id),
+        "D=-1".to_string(),
+        format!("({}.cont)", id),
+        "@SP".to_string(),
+        "A=M-1".to_string(),
+        "M=D".to_string(),
+        "@SP".to_string(),
+        "M=M-1".to_string(),
+        "A=M".to_string(),
+        "D=M".to_string(),
+    ];
+    let matches = matches!(condition, "D;JEQ" | "D;JGT" | "D;JLT")
+        && window[2..].iter().zip(&expected).all(|((_, x), y)| x == y)
+        && target.starts_with('@')
+        && window[COMPARE_BRANCH_LEN - 1].1 == "D;JNE";
+    matches.then_some((condition, target))
+}
+
+/// Makes comparisons directly followed by an if-goto jump to its target
+/// on the compared difference instead of materializing the boolean on the stack
+/// for the if-goto to pop. The comparison's labels are only jumped to from within
+/// itself, so nothing else relies on the removed code
+fn fuse_compare_branches(lines: &mut Vec<&str>) {
+    let mut i = 0;
+    while i < lines.len() {
+        let window = window(lines, i, COMPARE_BRANCH_LEN);
+        match compare_branch(&window).filter(|_| !is_inert(lines[i])) {
+            // pop the slot the result would have been stored to,
+            // the difference is still in D
+            Some((condition, target)) => {
+                i = replace(lines, i, &window, &["@SP", "M=M-1", target, condition])
+            }
+            None => i += 1,
+        }
+    }
+}
+
+/// Optimizes the generated code with local rewrites
+/// of common instruction sequences
+pub fn peephole(code: &str) -> String {
+    let mut lines = code.lines().collect::<Vec<&str>>();
+    fuse_compare_branches(&mut lines);
+    elide_push_pop(&mut lines);
+    lines.join("\n") + "\n"
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{count_instructions, run_vm, translate, Emulator, Options};
+
+    /// Runs the program translated with and without optimizing
+    fn run_both(files: &[(&str, &str)]) -> (Emulator, Emulator) {
+        let program = files
+            .iter()
+            .map(|(name, source)| (name.to_string(), source.to_string()))
+            .collect::<Vec<(String, String)>>();
+        let run = |options: Options| {
+            let emulator = run_vm(program.clone(), &options, 100_000).unwrap();
+            assert!(emulator.halted());
+            emulator
+        };
+        (
+            run(Options::default()),
+            run(Options::default().optimize(true)),
+        )
+    }
+
+    /// Checks that both runs leave the same pointers and stack, leaving out the
+    /// scratch registers holding return addresses, which move as the code shrinks
+    fn assert_same_state(files: &[(&str, &str)]) {
+        let (plain, optimized) = run_both(files);
+        let sp = plain.ram()[0] as usize;
+        assert_eq!(plain.ram()[..13], optimized.ram()[..13]);
+        assert_eq!(plain.ram()[256..sp], optimized.ram()[256..sp]);
+    }
+
+    #[test]
+    fn optimized_fixtures_behave_the_same() {
+        assert_same_state(&[("function_test", include_str!("../tests/function_test.vm"))]);
+        assert_same_state(&[
+            ("Main", include_str!("../tests/cross_file/Main.vm")),
+            ("Sys", include_str!("../tests/cross_file/Sys.vm")),
+        ]);
+    }
+
+    #[test]
+    fn optimized_branches_and_pops_behave_the_same() {
+        let source = "function Sys.init 1\n\
+                      push constant 10\npop local 0\n\
+                      label loop\n\
+                      push local 0\npush constant 1\nsub\npop local 0\n\
+                      push local 0\npush constant 3\ngt\nif-goto loop\n\
+                      push local 0\npush constant 3\neq\n\
+                      label end\ngoto end\n";
+        assert_same_state(&[("Sys", source)]);
+        let (_, optimized) = run_both(&[("Sys", source)]);
+        assert_eq!(optimized.top(), Some(-1));
+        let program = || vec![("Sys".to_string(), source.to_string())];
+        let plain = translate(program(), &Options::default()).unwrap();
+        let shorter = translate(program(), &Options::default().optimize(true)).unwrap();
+        assert!(count_instructions(&shorter) < count_instructions(&plain));
+    }
+
+    #[test]
+    fn peephole_elides_push_pop() {
+        let code = "@SP\nM=M+1\nA=M-1\nM=D\n// pop\n@SP\nM=M-1\nA=M\nD=M\n";
+        assert_eq!(peephole(code), "// pop\n@SP\nA=M\n");
+    }
+}
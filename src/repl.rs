@@ -0,0 +1,111 @@
+use crate::{generate_code, parse, Hack, Options, ReturnLabelAllocator, TranslateError};
+
+/// The state carried between the lines given to [`translate_line`]:
+/// the file they are part of, the function being defined and the next instruction id
+pub struct TranslateContext {
+    file: String,
+    options: Options,
+    frame: Option<String>,
+    next_id: usize,
+    line: usize,
+    return_labels: ReturnLabelAllocator,
+}
+
+impl TranslateContext {
+    /// Returns the context of a new session translating lines of the given file
+    pub fn new(file: &str, options: Options) -> Self {
+        TranslateContext {
+            file: file.to_string(),
+            options,
+            frame: None,
+            next_id: 0,
+            line: 0,
+            return_labels: ReturnLabelAllocator::default(),
+        }
+    }
+
+    /// Returns the function the following lines are part of,
+    /// the one last defined, or None before the first `function`
+    pub fn frame(&self) -> Option<&str> {
+        self.frame.as_deref()
+    }
+
+    /// Returns the id the next instruction will be numbered with
+    pub fn next_id(&self) -> usize {
+        self.next_id
+    }
+}
+
+/// Translates a single line of VM code, continuing the translation of the previous
+/// lines given with the context so that their labels don't collide. Lines with no
+/// instruction translate to nothing, and lines that fail to translate leave the frame
+/// and id unchanged. Calls are qualified with the class part of the function name,
+/// as the files defining them aren't known
+pub fn translate_line(line: &str, ctx: &mut TranslateContext) -> Result<String, TranslateError> {
+    ctx.line += 1;
+    let Some(instruction) = parse(&ctx.file, line).next() else {
+        return Ok(String::new());
+    };
+    let mut instruction = instruction?;
+    instruction.id = ctx.next_id;
+    instruction.label_id = ctx.next_id;
+    instruction.line = ctx.line;
+    instruction.frame = match instruction.operation {
+        "function" => instruction.arg1,
+        _ => ctx.frame.as_deref(),
+    };
+    if instruction.operation == "call" {
+        instruction.callee_file = instruction
+            .arg1
+            .and_then(|x| x.split_once('.'))
+            .map(|(class, _)| class);
+    }
+    let code = generate_code(&Hack, &instruction, &ctx.options, &mut ctx.return_labels)
+        .map_err(TranslateError::Instruction)?;
+    let frame = instruction.frame.map(str::to_string);
+    ctx.frame = frame;
+    ctx.next_id += 1;
+    Ok(match code.is_empty() {
+        true => code,
+        false => code + "\n",
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn context_follows_the_functions() {
+        let mut ctx = TranslateContext::new("Main", Options::default());
+        assert_eq!(translate_line("// nothing", &mut ctx), Ok(String::new()));
+        assert_eq!((ctx.frame(), ctx.next_id()), (None, 0));
+        translate_line("function Main.main 0", &mut ctx).unwrap();
+        translate_line("push constant 1", &mut ctx).unwrap();
+        assert_eq!((ctx.frame(), ctx.next_id()), (Some("Main.main"), 2));
+    }
+
+    #[test]
+    fn failed_lines_leave_the_context_unchanged() {
+        let mut ctx = TranslateContext::new("Main", Options::default());
+        translate_line("function Main.main 0", &mut ctx).unwrap();
+        assert!(translate_line("pop constant 1", &mut ctx).is_err());
+        assert!(translate_line("function", &mut ctx).is_err());
+        assert_eq!((ctx.frame(), ctx.next_id()), (Some("Main.main"), 1));
+    }
+
+    #[test]
+    fn lines_get_distinct_labels() {
+        let mut ctx = TranslateContext::new("Main", Options::default());
+        let first = translate_line("eq", &mut ctx).unwrap();
+        let second = translate_line("eq", &mut ctx).unwrap();
+        let labels = |code: &str| {
+            code.lines()
+                .filter(|x| x.starts_with('('))
+                .map(str::to_string)
+                .collect::<Vec<String>>()
+        };
+        assert!(!labels(&first).is_empty());
+        assert!(labels(&first).iter().all(|x| !labels(&second).contains(x)));
+    }
+}
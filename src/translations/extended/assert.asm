@@ -0,0 +1,15 @@
+@SP
+M=M-1
+A=M
+D=M
+@{}.fail
+D;JEQ
+@{}.ok
+0;JMP
+({}.fail)
+@{}
+M=-1
+({}.halt)
+@{}.halt
+0;JMP
+({}.ok)
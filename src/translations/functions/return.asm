@@ -3,7 +3,7 @@ D=A
 @LCL
 A=M-D
 D=M
-@R14
+@{}
 M=D
 
 @SP
@@ -20,32 +20,32 @@ M=D+1
 
 @LCL
 D=M
-@R13
+@{}
 M=D-1
 A=M
 D=M
 @THAT
 M=D
 
-@R13
+@{}
 M=M-1
 A=M
 D=M
 @THIS
 M=D
 
-@R13
+@{}
 M=M-1
 A=M
 D=M
 @ARG
 M=D
-@R13
+@{}
 M=M-1
 A=M
 D=M
 @LCL
 M=D
-@R14
+@{}
 A=M
 0;JMP
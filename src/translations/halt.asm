@@ -0,0 +1,3 @@
+({})
+@{}
+0;JMP
@@ -8,5 +8,5 @@ M=D
 D=A
 @ARG
 M=D
-@Sys.init
+@{}
 0;JMP
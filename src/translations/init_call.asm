@@ -0,0 +1,4 @@
+@256
+D=A
+@SP
+M=D
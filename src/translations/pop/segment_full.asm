@@ -2,12 +2,12 @@
 D=M
 @{}
 D=D+A
-@R13
+@{}
 M=D
 @SP
 M=M-1
 A=M
 D=M
-@R13
+@{}
 A=M
 M=D
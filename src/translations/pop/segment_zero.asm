@@ -0,0 +1,7 @@
+@SP
+M=M-1
+A=M
+D=M
+@{}
+A=M
+M=D
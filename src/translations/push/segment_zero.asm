@@ -0,0 +1,3 @@
+@{}
+A=M
+D=M
@@ -0,0 +1,105 @@
+/// The computations of the Hack C-instruction, with the commutative operations
+/// accepted with their operands either way round
+const COMPUTATIONS: [&str; 34] = [
+    "0", "1", "-1", "D", "A", "!D", "!A", "-D", "-A", "D+1", "A+1", "D-1", "A-1", "D+A", "A+D",
+    "D-A", "A-D", "D&A", "A&D", "D|A", "A|D", "M", "!M", "-M", "M+1", "M-1", "D+M", "M+D", "D-M",
+    "M-D", "D&M", "M&D", "D|M", "M|D",
+];
+
+/// The jump conditions of the Hack C-instruction
+const JUMPS: [&str; 7] = ["JGT", "JEQ", "JGE", "JLT", "JNE", "JLE", "JMP"];
+
+/// Returns whether the name is a valid Hack symbol:
+/// letters, digits, `_`, `.`, `$` and `:`, not starting with a digit
+pub(crate) fn is_symbol(name: &str) -> bool {
+    !name.is_empty()
+        && !name.starts_with(|c: char| c.is_ascii_digit())
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "_.$:".contains(c))
+}
+
+/// Checks a single line of Hack assembly, with comments and whitespace removed
+fn validate_line(line: &str) -> Result<(), String> {
+    if let Some(label) = line.strip_prefix('(') {
+        return match label.strip_suffix(')') {
+            Some(name) if is_symbol(name) => Ok(()),
+            _ => Err(format!("invalid label definition '{}'", line)),
+        };
+    }
+    if let Some(value) = line.strip_prefix('@') {
+        return match value.parse::<u16>() {
+            Ok(n) if n < 32768 => Ok(()),
+            Ok(_) => Err(format!("constant out of range in '{}'", line)),
+            Err(_) if is_symbol(value) => Ok(()),
+            Err(_) => Err(format!("invalid A-instruction '{}'", line)),
+        };
+    }
+    let (dest, rest) = line.split_once('=').unwrap_or(("", line));
+    let (comp, jump) = rest.split_once(';').unwrap_or((rest, ""));
+    let mut seen = String::new();
+    for c in dest.chars() {
+        if !"ADM".contains(c) || seen.contains(c) {
+            Err(format!("invalid destination '{}' in '{}'", dest, line))?;
+        }
+        seen.push(c);
+    }
+    if !COMPUTATIONS.contains(&comp) {
+        Err(format!("invalid computation '{}' in '{}'", comp, line))?;
+    }
+    if !(jump.is_empty() || JUMPS.contains(&jump)) {
+        Err(format!("invalid jump '{}' in '{}'", jump, line))?;
+    }
+    if line.contains('=') && dest.is_empty() {
+        Err(format!("missing destination in '{}'", line))?;
+    }
+    Ok(())
+}
+
+/// Checks that the generated code is well-formed Hack assembly,
+/// returning an error for each malformed line
+pub fn validate_assembly(code: &str) -> Result<(), Vec<String>> {
+    let errors = code
+        .lines()
+        .enumerate()
+        .map(|(i, x)| (i + 1, x.split("//").next().unwrap().trim()))
+        .filter(|(_, x)| !x.is_empty())
+        .filter_map(|(n, x)| {
+            validate_line(x)
+                .err()
+                .map(|e| format!("generated line {}: {}", n, e))
+        })
+        .collect::<Vec<String>>();
+    match errors.is_empty() {
+        true => Ok(()),
+        false => Err(errors),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn accepts_well_formed_lines() {
+        let code = "(Main.main$ret.0)\n@32767\n@SP\nAM=M-1 // pop\nD=D+M\nMD=M+D\n0;JMP\nD;JLE\n";
+        assert_eq!(validate_assembly(code), Ok(()));
+    }
+
+    #[test]
+    fn reports_each_malformed_line() {
+        let code = "@32768\n(1st)\n@x-y\nDD=A\nD=X\nD;JUMP\n=A\n";
+        assert_eq!(
+            validate_assembly(code),
+            Err(vec![
+                "generated line 1: constant out of range in '@32768'".to_string(),
+                "generated line 2: invalid label definition '(1st)'".to_string(),
+                "generated line 3: invalid A-instruction '@x-y'".to_string(),
+                "generated line 4: invalid destination 'DD' in 'DD=A'".to_string(),
+                "generated line 5: invalid computation 'X' in 'D=X'".to_string(),
+                "generated line 6: invalid jump 'JUMP' in 'D;JUMP'".to_string(),
+                "generated line 7: missing destination in '=A'".to_string(),
+            ])
+        );
+    }
+}
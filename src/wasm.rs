@@ -0,0 +1,13 @@
+use crate::{translate, Options};
+
+/// Translates the VM source of a single file into Hack assembly
+/// using the default options, joining any errors into one message.
+/// Only takes and returns strings so that it can be exported with
+/// `wasm-bindgen` to run the translator in the browser.
+pub fn translate_wasm(file: &str, source: &str) -> Result<String, String> {
+    translate(
+        vec![(file.to_string(), source.to_string())],
+        &Options::default(),
+    )
+    .map_err(|e| e.join("\n"))
+}
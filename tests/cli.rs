@@ -0,0 +1,356 @@
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output, Stdio};
+
+/// Returns a new empty directory under the system's temporary directory
+fn scratch_dir(name: &str) -> PathBuf {
+    let dir =
+        std::env::temp_dir().join(format!("vmtranslator-cli-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// Runs the translator with the arguments, from the directory
+fn run(dir: &Path, args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_vmtranslator"))
+        .args(args)
+        .current_dir(dir)
+        .env_remove("NO_COLOR")
+        .output()
+        .unwrap()
+}
+
+/// Runs the translator with the arguments, from the directory, feeding it the input
+fn run_with_stdin(dir: &Path, args: &[&str], input: &str) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_vmtranslator"))
+        .args(args)
+        .current_dir(dir)
+        .env_remove("NO_COLOR")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(input.as_bytes())
+        .unwrap();
+    child.wait_with_output().unwrap()
+}
+
+/// Returns what the translator printed to stderr
+fn stderr(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stderr).into_owned()
+}
+
+#[test]
+fn extensionless_file_needs_force() {
+    let dir = scratch_dir("extensionless");
+    fs::write(dir.join("Prog"), "function Sys.init 0\n").unwrap();
+    let output = run(&dir, &["Prog"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(
+        stderr(&output),
+        "Input file Prog has to be a .vm file or a directory, use --force to translate it anyway\n"
+    );
+    assert!(run(&dir, &["Prog", "--force"]).status.success());
+    assert!(dir.join("Prog.asm").is_file());
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[cfg(unix)]
+#[test]
+fn non_utf8_file_name_is_reported() {
+    use std::ffi::OsStr;
+    use std::os::unix::ffi::OsStrExt;
+    let dir = scratch_dir("non-utf8");
+    let name = OsStr::from_bytes(b"Bad\xff.vm");
+    if fs::write(dir.join(name), "function Sys.init 0\n").is_err() {
+        // the filesystem only takes UTF-8 names
+        return;
+    }
+    let output = run(&dir, &["."]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(
+        stderr(&output).ends_with("has no valid UTF-8 name\n"),
+        "{}",
+        stderr(&output)
+    );
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn explain_fails_on_an_unknown_opcode() {
+    let dir = scratch_dir("explain");
+    let output = run(&dir, &["--explain", "bogus"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(output.stdout.is_empty());
+    assert!(!stderr(&output).is_empty());
+    let output = run(&dir, &["--explain", "push"]);
+    assert!(output.status.success());
+    assert!(String::from_utf8_lossy(&output.stdout).contains("@SP"));
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn unwritable_intermediates_are_reported() {
+    let dir = scratch_dir("intermediates");
+    fs::write(dir.join("Sys.vm"), "function Sys.init 0\n").unwrap();
+    let output = run(&dir, &["Sys.vm", "--emit-intermediates", "Sys.vm/parts"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(
+        stderr(&output).starts_with("Unable to create Sys.vm/parts: "),
+        "{}",
+        stderr(&output)
+    );
+    assert!(run(&dir, &["Sys.vm", "--emit-intermediates", "parts"])
+        .status
+        .success());
+    assert_eq!(
+        fs::read_to_string(dir.join("parts/Sys.asm"))
+            .unwrap()
+            .lines()
+            .next(),
+        Some("// function Sys.init 0")
+    );
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[cfg(unix)]
+#[test]
+fn self_referential_symlink_is_skipped() {
+    use std::os::unix::fs::symlink;
+    let dir = scratch_dir("symlinks");
+    fs::write(dir.join("Sys.vm"), "function Sys.init 0\n").unwrap();
+    symlink("Loop.vm", dir.join("Loop.vm")).unwrap();
+    symlink("Sys.vm", dir.join("Tail.vm")).unwrap();
+    let output = run(&dir, &[".", "--follow-symlinks"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(
+        stderr(&output).starts_with("Skipping ./Loop.vm: "),
+        "{}",
+        stderr(&output)
+    );
+    // the link to an already read file isn't read again
+    let asm = fs::read_to_string(dir.join(format!(
+        "{}.asm",
+        dir.file_name().unwrap().to_str().unwrap()
+    )))
+    .unwrap();
+    assert_eq!(asm.matches("(Sys.init)").count(), 1);
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn crlf_applies_to_files_and_stdout() {
+    let dir = scratch_dir("crlf");
+    fs::write(dir.join("Sys.vm"), "function Sys.init 0\npush constant 1\n").unwrap();
+    let is_crlf = |code: &str| {
+        !code.is_empty()
+            && code
+                .match_indices('\n')
+                .all(|(i, _)| code[..i].ends_with('\r'))
+    };
+    assert!(run(&dir, &["Sys.vm", "--line-ending", "crlf"])
+        .status
+        .success());
+    assert!(is_crlf(&fs::read_to_string(dir.join("Sys.asm")).unwrap()));
+    let output = run_with_stdin(
+        &dir,
+        &["--files-from", "-", "--line-ending", "crlf"],
+        "Sys.vm\n",
+    );
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(is_crlf(&String::from_utf8(output.stdout).unwrap()));
+}
+
+#[test]
+fn color_flag_controls_escape_codes() {
+    let dir = scratch_dir("color");
+    fs::write(dir.join("Sys.vm"), "function Sys.init 0\n").unwrap();
+    fs::write(dir.join("Bad.vm"), "function Sys.init 0\nfrobnicate\n").unwrap();
+    let colored = |file: &str, color: &str, no_color: bool| {
+        let mut command = Command::new(env!("CARGO_BIN_EXE_vmtranslator"));
+        command.args([file, "--color", color]).current_dir(&dir);
+        match no_color {
+            true => command.env("NO_COLOR", "1"),
+            false => command.env_remove("NO_COLOR"),
+        };
+        let output = command.output().unwrap();
+        let printed = [output.stdout, output.stderr].concat();
+        String::from_utf8(printed).unwrap().contains('\u{1b}')
+    };
+    for file in ["Sys.vm", "Bad.vm"] {
+        // output is piped here, so auto never colors
+        assert!(!colored(file, "auto", false));
+        assert!(!colored(file, "never", false));
+        assert!(colored(file, "always", false));
+        // an explicit choice wins over NO_COLOR
+        assert!(colored(file, "always", true));
+        assert!(!colored(file, "never", true));
+    }
+}
+
+#[test]
+fn os_functions_are_callable() {
+    let dir = scratch_dir("os-dir");
+    fs::create_dir_all(dir.join("Prog")).unwrap();
+    fs::create_dir_all(dir.join("os")).unwrap();
+    fs::write(
+        dir.join("Prog/Sys.vm"),
+        "function Sys.init 0\npush constant 2\ncall Math.double 1\nlabel end\ngoto end\n",
+    )
+    .unwrap();
+    fs::write(
+        dir.join("os/Math.vm"),
+        "function Math.double 0\npush argument 0\npop static 0\npush static 0\npush static 0\nadd\nreturn\n",
+    )
+    .unwrap();
+    let output = run(&dir, &["Prog", "--os-dir", "os"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    let code = fs::read_to_string(dir.join("Prog/Prog.asm")).unwrap();
+    assert!(code.contains("(Math.double)") && code.contains("@Math.double"));
+    assert!(code.contains("@Math.0") && !code.contains("@Sys.0"));
+    assert!(code.find("(Sys.init)") < code.find("(Math.double)"));
+}
+
+#[test]
+fn count_only_reports_the_counts() {
+    let dir = scratch_dir("count-only");
+    fs::write(
+        dir.join("Sys.vm"),
+        "function Sys.init 0\npush constant 1\nlabel end\ngoto end\n",
+    )
+    .unwrap();
+    let output = run(&dir, &["Sys.vm", "--count-only"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    // 11 in the bootstrap, then 5 for function, 7 for push and 2 for goto
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "VM instructions: 4\nAssembly instructions: 25\nLabels: 2\n"
+    );
+    assert!(!dir.join("Sys.asm").exists());
+}
+
+#[test]
+fn files_from_translates_the_listed_files_in_order() {
+    let dir = scratch_dir("files-from");
+    fs::create_dir_all(dir.join("a")).unwrap();
+    fs::create_dir_all(dir.join("b")).unwrap();
+    fs::write(
+        dir.join("a/Sys.vm"),
+        "function Sys.init 0\ncall Main.main 0\n",
+    )
+    .unwrap();
+    fs::write(
+        dir.join("b/Main.vm"),
+        "function Main.main 0\npush constant 1\nreturn\n",
+    )
+    .unwrap();
+    let list = "# translated in this order\n\n  b/Main.vm\na/Sys.vm\n";
+    fs::write(dir.join("list.txt"), list).unwrap();
+    let output = run(&dir, &["--files-from", "list.txt"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    let code = fs::read_to_string(dir.join("list.asm")).unwrap();
+    assert!(code.find("(Main.main)") < code.find("(Sys.init)"));
+    let piped = run_with_stdin(&dir, &["--files-from", "-"], list);
+    assert!(piped.status.success(), "{}", stderr(&piped));
+    assert_eq!(String::from_utf8(piped.stdout).unwrap(), code);
+}
+
+#[test]
+fn dump_tokens_shows_the_extra_tokens() {
+    let dir = scratch_dir("dump-tokens");
+    fs::write(
+        dir.join("Main.vm"),
+        "// Main.vm\nreturn\n  push constant 7 8 nine // trailing\n",
+    )
+    .unwrap();
+    let output = run(&dir, &["Main.vm", "--dump-tokens"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "line 2: op='return' arg1=None arg2=None extra=[]\n\
+         line 3: op='push' arg1='constant' arg2='7' extra=['8', 'nine']\n"
+    );
+    assert!(!dir.join("Main.asm").exists());
+}
+
+#[test]
+fn repl_streams_each_line_and_survives_errors() {
+    let dir = scratch_dir("repl");
+    let output = run_with_stdin(
+        &dir,
+        &["--repl"],
+        "function Sys.init 0\nbogus\npush local 0\n",
+    );
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(
+        stderr(&output),
+        "Repl.vm:2:1 #1 'bogus': Invalid VM instruction 'bogus'\n"
+    );
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let function = stdout.find("// function Sys.init 0\n(Sys.init)\n").unwrap();
+    let push = stdout.find("// push local 0\n@LCL\n").unwrap();
+    assert!(function < push);
+}
+
+#[test]
+fn symbols_resolve_calls_to_other_files() {
+    let dir = scratch_dir("symbols");
+    fs::write(
+        dir.join("Main.vm"),
+        "function Main.main 0\ncall Lib.helper 0\nreturn\n",
+    )
+    .unwrap();
+    let output = run(&dir, &["Main.vm", "--check"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "undefined reference to 'Lib.helper' from Main.vm\n"
+    );
+    fs::write(dir.join("lib.txt"), "# defined by Lib.vm\nLib.helper\n").unwrap();
+    let output = run(&dir, &["Main.vm", "--check", "--symbols", "lib.txt"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(run(&dir, &["Main.vm", "--symbols", "lib.txt"])
+        .status
+        .success());
+    assert!(dir.join("Main.asm").is_file());
+}
+
+#[test]
+fn output_dir_mirrors_the_listed_directories() {
+    let dir = scratch_dir("output-dir");
+    fs::create_dir_all(dir.join("src/a")).unwrap();
+    fs::write(
+        dir.join("src/Sys.vm"),
+        "function Sys.init 0\ncall Foo.f 0\n",
+    )
+    .unwrap();
+    fs::write(
+        dir.join("src/a/Foo.vm"),
+        "function Foo.f 0\npush constant 1\nreturn\n",
+    )
+    .unwrap();
+    fs::write(dir.join("list.txt"), "src/Sys.vm\n./src/a/Foo.vm\n").unwrap();
+    let output = run(&dir, &["--files-from", "list.txt", "--output-dir", "out"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "Successfully translated list.txt into out\n"
+    );
+    let foo = fs::read_to_string(dir.join("out/src/a/Foo.asm")).unwrap();
+    assert!(foo.starts_with("// function Foo.f 0\n(Foo.f)\n"));
+    let sys = fs::read_to_string(dir.join("out/src/Sys.asm")).unwrap();
+    assert!(sys.contains("(Sys.init)") && !sys.contains("(Foo.f)"));
+    assert!(!dir.join("list.asm").exists());
+    // a directory's files are all in the output directory itself
+    assert!(run(&dir.join("src"), &["a", "--output-dir", "../flat"])
+        .status
+        .success());
+    assert!(dir.join("flat/Foo.asm").is_file());
+}
@@ -0,0 +1,171 @@
+@261
+D=A
+@SP
+M=D
+@LCL
+M=D
+@256
+D=A
+@ARG
+M=D
+@Sys.Sys.init
+0;JMP
+// function Main.double 0
+(Main.Main.double)
+@0
+D=A
+@SP
+M=M+D
+A=M-D
+
+// push argument 0
+@ARG
+D=M
+@0
+A=D+A
+D=M
+@SP
+M=M+1
+A=M-1
+M=D
+
+// push argument 0
+@ARG
+D=M
+@0
+A=D+A
+D=M
+@SP
+M=M+1
+A=M-1
+M=D
+
+// add
+@SP
+M=M-1
+A=M
+D=M
+A=A-1
+M=M+D
+
+// return
+@5
+D=A
+@LCL
+A=M-D
+D=M
+@R14
+M=D
+
+@SP
+A=M-1
+D=M
+@ARG
+A=M
+M=D
+
+@ARG
+D=M
+@SP
+M=D+1
+
+@LCL
+D=M
+@R13
+M=D-1
+A=M
+D=M
+@THAT
+M=D
+
+@R13
+M=M-1
+A=M
+D=M
+@THIS
+M=D
+
+@R13
+M=M-1
+A=M
+D=M
+@ARG
+M=D
+@R13
+M=M-1
+A=M
+D=M
+@LCL
+M=D
+@R14
+A=M
+0;JMP
+
+// function Sys.init 0
+(Sys.Sys.init)
+@0
+D=A
+@SP
+M=M+D
+A=M-D
+
+// push constant 4
+@4
+D=A
+@SP
+M=M+1
+A=M-1
+M=D
+
+// call Main.double 1
+@Sys.Sys.init$ret.0
+D=A
+@SP
+M=M+1
+A=M-1
+M=D
+@LCL
+D=M
+@SP
+M=M+1
+A=M-1
+M=D
+@ARG
+D=M
+@SP
+M=M+1
+A=M-1
+M=D
+@THIS
+D=M
+@SP
+M=M+1
+A=M-1
+M=D
+@THAT
+D=M
+@SP
+M=M+1
+A=M-1
+M=D
+@6
+D=A
+@SP
+D=M-D
+@ARG
+M=D
+@SP
+D=M
+@LCL
+M=D
+@Main.Main.double
+0;JMP
+(Sys.Sys.init$ret.0)
+
+// label loop
+(Sys.Sys.init$loop)
+
+// goto loop
+@Sys.Sys.init$loop
+0;JMP
+
@@ -1,58 +1,15 @@
-@256
+@261
 D=A
 @SP
 M=D
-@global$ret.0
-D=A
-@SP
-M=M+1
-A=M-1
-M=D
-
 @LCL
-D=M
-@SP
-M=M+1
-A=M-1
-M=D
-
-@ARG
-D=M
-@SP
-M=M+1
-A=M-1
-M=D
-
-@THIS
-D=M
-@SP
-M=M+1
-A=M-1
 M=D
-
-@THAT
-D=M
-@SP
-M=M+1
-A=M-1
-M=D
-
-@5
+@256
 D=A
-@SP
-D=M-D
 @ARG
 M=D
-
-@SP
-D=M
-@LCL
-M=D
-
 @Sys.init
 0;JMP
-
-(global$ret.0)
 // function Sys.init 0
 (Sys.init)
 @0
@@ -78,57 +35,49 @@ A=M-1
 M=D
 
 // call add_three 2
-@Sys.init$ret.3
+@Sys.init$ret.0
 D=A
 @SP
 M=M+1
 A=M-1
 M=D
-
 @LCL
 D=M
 @SP
 M=M+1
 A=M-1
 M=D
-
 @ARG
 D=M
 @SP
 M=M+1
 A=M-1
 M=D
-
 @THIS
 D=M
 @SP
 M=M+1
 A=M-1
 M=D
-
 @THAT
 D=M
 @SP
 M=M+1
 A=M-1
 M=D
-
 @7
 D=A
 @SP
 D=M-D
 @ARG
 M=D
-
 @SP
 D=M
 @LCL
 M=D
-
 @add_three
 0;JMP
-
-(Sys.init$ret.3)
+(Sys.init$ret.0)
 
 // label loop
 (function_test.Sys.init$loop)
@@ -138,6 +87,14 @@ M=D
 0;JMP
 
 // return
+@5
+D=A
+@LCL
+A=M-D
+D=M
+@R14
+M=D
+
 @SP
 A=M-1
 D=M
@@ -172,16 +129,13 @@ A=M
 D=M
 @ARG
 M=D
-
 @R13
 M=M-1
 A=M
 D=M
 @LCL
 M=D
-
-@R13
-A=M-1
+@R14
 A=M
 0;JMP
 
@@ -228,6 +182,14 @@ A=A-1
 M=M+D
 
 // return
+@5
+D=A
+@LCL
+A=M-D
+D=M
+@R14
+M=D
+
 @SP
 A=M-1
 D=M
@@ -262,17 +224,13 @@ A=M
 D=M
 @ARG
 M=D
-
 @R13
 M=M-1
 A=M
 D=M
 @LCL
 M=D
-
-@R13
-A=M-1
+@R14
 A=M
 0;JMP
 
-